@@ -0,0 +1,42 @@
+use fax::decoder::{decode_g4, pels_packed};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::read_pbm;
+
+/// Decode a Group 4 stream repeatedly and report the throughput.
+///
+/// The decoded image is first compared with the reference PBM,
+/// which is expected to be the output of an established decoder (for example `tiff2pnm` of libtiff).
+///
+/// usage: bench <g4 stream> <reference pbm> [iterations]
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let input = args.next().unwrap();
+    let reference = args.next().unwrap();
+    let iterations: u32 = args.next().map(|s| s.parse().unwrap()).unwrap_or(100);
+
+    let data = fs::read(&input).unwrap();
+    let pbm = read_pbm(Path::new(&reference));
+    let (width, ref_rows) = (pbm.width, pbm.rows);
+
+    let mut rows = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| rows.push(pels_packed(line, width))).unwrap();
+    for (y, (row, ref_row)) in rows.iter().zip(ref_rows.iter()).enumerate() {
+        assert!(row == ref_row, "line {} differs from the reference", y);
+    }
+    // the reference may have trailing lines that are not coded
+    assert!(rows.len() <= ref_rows.len());
+
+    let start = Instant::now();
+    let mut lines = 0u64;
+    for _ in 0 .. iterations {
+        decode_g4(data.iter().cloned(), width, None, |_| lines += 1).unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let pixels = lines as f64 * width as f64;
+    println!("{} lines of {} pixels: {:.1} MP/s", rows.len(), width, pixels / elapsed / 1e6);
+}
@@ -0,0 +1,68 @@
+//! Decoding without `std` and without an allocator, as on small targets.
+//!
+//! All buffers are fixed-size arrays on the stack. Build it for a Cortex-M3 with
+//!
+//!     cargo build --example embedded --no-default-features --target thumbv7m-none-eabi
+//!
+//! There is no runtime crate, so `_start` is not called by a vector table.
+//! On a board, it is the reset handler (for example the `#[entry]` of `cortex-m-rt`).
+//! On other targets, the example runs as a normal program.
+
+#![cfg_attr(target_os = "none", no_std, no_main)]
+
+use core::fmt::Write;
+use fax::decoder::{decode_g4_fixed, write_pels_packed};
+
+const WIDTH: u16 = 264;
+static IMAGE: &[u8] = include_bytes!("../stream/6");
+
+/// The debug UART, here the data register of UART0 of the LM3S6965 (as emulated by QEMU).
+struct Uart;
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        #[cfg(target_os = "none")]
+        for b in s.bytes() {
+            // SAFETY: the register is always mapped, and writing it sends the byte
+            unsafe { core::ptr::write_volatile(0x4000_C000 as *mut u32, b as u32) };
+        }
+        #[cfg(not(target_os = "none"))]
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+fn run() {
+    // the current and the reference line, with at most one change per pixel each
+    let mut buffer = [0u16; 2 * WIDTH as usize];
+    let mut row = [0u8; (WIDTH as usize + 7) / 8];
+    let mut lines = 0u32;
+    let mut black = 0u32;
+
+    let result = decode_g4_fixed(&mut buffer, IMAGE.iter().cloned(), WIDTH, None, |line| {
+        write_pels_packed(line, WIDTH, &mut row).unwrap();
+        black += row.iter().map(|b| b.count_ones()).sum::<u32>();
+        lines += 1;
+    });
+    match result {
+        Ok(()) => writeln!(Uart, "decoded {} lines, {} black pixels", lines, black),
+        Err(e) => writeln!(Uart, "decoding failed after {} lines: {}", lines, e),
+    }.unwrap();
+}
+
+#[cfg(target_os = "none")]
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    run();
+    loop {}
+}
+
+#[cfg(target_os = "none")]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+#[cfg(not(target_os = "none"))]
+fn main() {
+    run();
+}
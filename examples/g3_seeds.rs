@@ -0,0 +1,102 @@
+use fax::{VecWriter, BitWriter, Bits, encoder::G3Encoder};
+use std::fs;
+use std::path::Path;
+
+const EOL: Bits = Bits { data: 1, len: 12 };
+
+/// Inserts fill bits in front of each EOL, so that it ends at a byte boundary.
+struct Fill {
+    writer: VecWriter,
+    bits: usize,
+}
+impl BitWriter for Fill {
+    fn write(&mut self, bits: Bits) {
+        if bits == EOL {
+            while (self.bits + 12) % 8 != 0 {
+                self.writer.write(Bits { data: 0, len: 1 });
+                self.bits += 1;
+            }
+        }
+        self.writer.write(bits);
+        self.bits += bits.len as usize;
+    }
+}
+
+/// Update the HDLC frame check sequence (CRC-16 as of ITU-T V.42) with `byte`.
+fn fcs_update(fcs: u16, byte: u8) -> u16 {
+    let mut fcs = fcs ^ byte as u16;
+    for _ in 0 .. 8 {
+        fcs = if fcs & 1 == 1 { (fcs >> 1) ^ 0x8408 } else { fcs >> 1 };
+    }
+    fcs
+}
+
+/// Frame the data as T.30 facsimile coded data (FCD) frames of 256 bytes, as sent in error correction mode.
+///
+/// This is the raw bit stream `t30::HdlcUnstuffer` reads, with the first bit in the least significant bit of each byte.
+fn hdlc(data: &[u8]) -> Vec<u8> {
+    const FLAG: [u8; 8] = [0, 1, 1, 1, 1, 1, 1, 0];
+    let mut bits = FLAG.to_vec();
+    for (n, chunk) in data.chunks(256).enumerate() {
+        // address, control, FCF and frame number, then the data with the first bit sent first
+        let mut frame = vec![0xFF, 0x03, 0x06, n as u8];
+        frame.extend(chunk.iter().map(|b| b.reverse_bits()));
+        let fcs = !frame.iter().fold(0xFFFF, |fcs, &b| fcs_update(fcs, b));
+        frame.extend_from_slice(&fcs.to_le_bytes());
+
+        let mut ones = 0;
+        for byte in frame {
+            for i in 0 .. 8 {
+                let bit = (byte >> i) & 1;
+                bits.push(bit);
+                ones = if bit == 1 { ones + 1 } else { 0 };
+                if ones == 5 {
+                    bits.push(0);
+                    ones = 0;
+                }
+            }
+        }
+        bits.extend_from_slice(&FLAG);
+    }
+    bits.chunks(8).map(|c| c.iter().enumerate().map(|(i, &b)| b << i).sum()).collect()
+}
+
+fn encode<W: BitWriter>(writer: W, lines: &[&[u16]], width: u16) -> G3Encoder<W> {
+    let mut encoder = G3Encoder::new(writer);
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder
+}
+
+/// Write the generated seeds for the decode_g3 and decode_g3_hdlc fuzz targets
+/// to the corresponding directories of the given corpus directory.
+///
+/// usage: g3_seeds <dir>
+fn main() {
+    let dir = std::env::args().nth(1).unwrap();
+    let dir = Path::new(&dir);
+    let mut seeds = vec![];
+
+    // leading EOL, one white line and the RTC
+    let data = encode(VecWriter::new(), &[&[]], 1728).finish().finish();
+    seeds.push(("minimal", data));
+
+    // white and black runs of 2560 + 63
+    let data = encode(VecWriter::new(), &[&[2623]], 5246).finish().finish();
+    seeds.push(("max-run", data));
+
+    let lines: Vec<Vec<u16>> = (0 .. 20).map(|y| vec![y, 3 * y + 20, 1000]).collect();
+    let lines: Vec<&[u16]> = lines.iter().map(|line| line.as_slice()).collect();
+    let writer = Fill { writer: VecWriter::new(), bits: 0 };
+    let data = encode(writer, &lines, 1728).finish().writer.finish();
+    seeds.push(("fill-bits", data));
+
+    for (target, frame) in [("decode_g3", false), ("decode_g3_hdlc", true)] {
+        let dir = dir.join(target);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, data) in &seeds {
+            fs::write(dir.join(name), if frame { hdlc(data) } else { data.clone() }).unwrap();
+        }
+    }
+}
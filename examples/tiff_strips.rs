@@ -0,0 +1,36 @@
+use std::fs;
+use tiff::{decoder::Decoder, tags::Tag};
+
+/// Write the raw data of each Group 4 coded strip in a TIFF file to its own file.
+///
+/// Strips with other compressions are skipped.
+/// The outputs are named `<prefix>-<page>-<strip>`.
+///
+/// usage: tiff_strips <tiff> <prefix>
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let input = args.next().unwrap();
+    let prefix = args.next().unwrap();
+
+    let data = fs::read(&input).unwrap();
+    let mut decoder = Decoder::new(std::io::Cursor::new(data.as_slice())).unwrap();
+    let mut page = 0;
+    loop {
+        let compression = decoder.get_tag_u32(Tag::Compression).unwrap();
+        if compression == 4 {
+            let offsets = decoder.get_tag_u32_vec(Tag::StripOffsets).unwrap();
+            let counts = decoder.get_tag_u32_vec(Tag::StripByteCounts).unwrap();
+            for (strip, (&offset, &count)) in offsets.iter().zip(counts.iter()).enumerate() {
+                let strip_data = &data[offset as usize .. (offset + count) as usize];
+                fs::write(format!("{}-{}-{}", prefix, page, strip), strip_data).unwrap();
+            }
+        } else {
+            eprintln!("{}: page {} has compression {}, skipped", input, page, compression);
+        }
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().unwrap();
+        page += 1;
+    }
+}
@@ -0,0 +1,15 @@
+#![no_main]
+use fax::decoder::{decode_g3, decode_g3_with_options, G3DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    decode_g3(data.iter().cloned(), |_| ());
+
+    let options = G3DecodeOptions {
+        width: Some(1728),
+        two_dimensional: true,
+        strict: true,
+        .. G3DecodeOptions::default()
+    };
+    let _ = decode_g3_with_options(data.iter().cloned(), &options, |_| ());
+});
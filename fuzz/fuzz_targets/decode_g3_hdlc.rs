@@ -0,0 +1,8 @@
+#![no_main]
+use fax::{decoder::decode_g3, t30::HdlcUnstuffer};
+use libfuzzer_sys::fuzz_target;
+
+// T.30 facsimile coded data frames, with the 4 bytes of address, control, FCF and frame number in front
+fuzz_target!(|data: &[u8]| {
+    decode_g3(HdlcUnstuffer::new(data.iter().cloned()).with_header_len(4), |_| ());
+});
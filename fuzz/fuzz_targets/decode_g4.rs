@@ -0,0 +1,7 @@
+#![no_main]
+use fax::decoder::decode_g4;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    decode_g4(data.iter().cloned(), 1728, None, |_| ());
+});
@@ -0,0 +1,46 @@
+#!/bin/sh
+# Generate the seed corpora for the decode_g4, decode_g3 and decode_g3_hdlc fuzz targets.
+#
+# usage: fuzz/gen_corpus.sh [tiff files...]
+#
+# The raw Group 4 streams are taken from the fixtures in `stream/` and from the
+# strips of the given TIFF files (for example the fax images of the libtiff or
+# Pillow test suites). Edge cases are generated on top of that.
+set -e
+
+root=$(cd "$(dirname "$0")/.." && pwd)
+out="$root/fuzz/corpus/decode_g4"
+mkdir -p "$out"
+
+for f in "$root"/stream/*; do
+    case "$f" in
+        *.*) ;;
+        *) cp "$f" "$out/stream-$(basename "$f")" ;;
+    esac
+done
+
+for tiff in "$@"; do
+    name=$(basename "$tiff" | sed 's/\.[^.]*$//')
+    cargo run --quiet --manifest-path "$root/Cargo.toml" --example tiff_strips -- "$tiff" "$out/$name"
+done
+
+# empty stream
+: > "$out/empty"
+# a single EOL
+printf '\000\020' > "$out/eol"
+# EOFB only
+printf '\000\020\001' > "$out/eofb"
+# all-white page of 2200 lines: one V0 code per line, then the EOFB
+{
+    head -c 275 /dev/zero | tr '\000' '\377'
+    printf '\000\020\001'
+} > "$out/white-page"
+# a horizontal code with runs of 64000 and 5120 pixels, which add up to more than 65535
+{
+    printf '\040'
+    for i in 1 2 3 4 5 6 7 8 9 10 11 12; do printf '\076\003\340'; done
+    printf '\076\152\003\340\076\033\200'
+} > "$out/horizontal-overflow"
+
+# Group 3 edge cases, as they are and in HDLC frames
+cargo run --quiet --manifest-path "$root/Cargo.toml" --example g3_seeds -- "$root/fuzz/corpus"
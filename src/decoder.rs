@@ -1,5 +1,8 @@
-use crate::{BitReader, ByteReader, Color, Transitions};
-use crate::maps::{Mode, black, white, mode, EDFB_HALF, EOL};
+use crate::{BitReader, ByteReader, Color, CodingScheme, FaxError, Transitions};
+use crate::maps::{black, white, mode, EDFB_HALF};
+#[cfg(feature = "std")]
+use crate::maps::EOL;
+pub use crate::maps::Mode;
 
 
 fn with_markup<D, R>(decoder: D, reader: &mut R) -> Option<u16>
@@ -8,7 +11,7 @@ fn with_markup<D, R>(decoder: D, reader: &mut R) -> Option<u16>
     let mut sum = 0;
     while let Some(n) = decoder(reader) {
         //print!("{} ", n);
-        sum += n;
+        sum = u16::checked_add(sum, n)?;
         if n < 64 {
             //println!("= {}", sum);
             return Some(sum)
@@ -25,20 +28,36 @@ fn colored(current: Color, reader: &mut impl BitReader) -> Option<u16> {
     }
 }
 
+/// `colored`, telling a code cut off by the end of the data apart from an invalid one.
+fn run(current: Color, reader: &mut impl BitReader) -> Result<u16, FaxError> {
+    colored(current, reader).ok_or_else(|| {
+        // no run code is longer than 13 bits
+        match reader.peek(13) {
+            None => FaxError::UnexpectedEof,
+            Some(_) => FaxError::InvalidCode,
+        }
+    })
+}
+
 /// Turn a list of color changing position into an iterator of pixel colors
 ///
 /// The width of the line/image has to be given in `width`.
 /// The iterator will produce exactly that many items.
 pub fn pels(line: &[u16], width: u16) -> impl Iterator<Item=Color> + '_ {
-    use std::iter::repeat;
+    pels_from(line.iter().cloned(), line.len(), width)
+}
+
+/// `pels` for any iterator over the `len` color changes of a line.
+fn pels_from(line: impl Iterator<Item=u16>, len: usize, width: u16) -> impl Iterator<Item=Color> {
+    use core::iter::repeat;
     let mut color = Color::White;
     let mut last = 0;
-    let pad_color = if line.len() & 1 == 1 {
+    let pad_color = if len & 1 == 1 {
         !color
     } else { 
         color
     };
-    line.iter().flat_map(move |&p| {
+    line.flat_map(move |p| {
         let c = color;
         color = !color;
         let n = p.saturating_sub(last);
@@ -47,39 +66,370 @@ pub fn pels(line: &[u16], width: u16) -> impl Iterator<Item=Color> + '_ {
     }).chain(repeat(pad_color)).take(width as usize)
 }
 
+/// Like `pels`, but yields `0x00` for black and `0xFF` for white pixels.
+/// 
+/// For `BlackIs1` data, invert the values with `!`.
+pub fn pels_u8(line: &[u16], width: u16) -> impl Iterator<Item=u8> + '_ {
+    pels(line, width).map(|c| match c {
+        Color::Black => 0x00,
+        Color::White => 0xFF,
+    })
+}
+
+/// Like `pels`, but yields `true` for black pixels.
+pub fn pels_bool(line: &[u16], width: u16) -> impl Iterator<Item=bool> + '_ {
+    pels(line, width).map(|c| c == Color::Black)
+}
+
+/// Write a line as packed pixels into `out`, one bit per pixel with `1` for black.
+/// 
+/// The most significant bit is the leftmost pixel and the last byte is padded with zeros.
+/// `out` has to hold at least `(width + 7) / 8` bytes, otherwise `FaxError::BufferTooSmall` is returned.
+pub fn write_pels_packed(line: &[u16], width: u16, out: &mut [u8]) -> Result<(), FaxError> {
+    let len = (width as usize).div_ceil(8);
+    let out = out.get_mut(.. len).ok_or(FaxError::BufferTooSmall)?;
+    for b in out.iter_mut() {
+        *b = 0;
+    }
+    let ends = line.iter().skip(1).step_by(2).cloned().chain(core::iter::once(width));
+    for (&start, end) in line.iter().step_by(2).zip(ends) {
+        for i in start as usize .. end.min(width) as usize {
+            out[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    Ok(())
+}
+
+/// Turn a line into packed pixels, one bit per pixel with `1` for black.
+/// 
+/// See `write_pels_packed` for details.
+#[cfg(feature = "std")]
+pub fn pels_packed(line: &[u16], width: u16) -> Vec<u8> {
+    let mut out = vec![0; (width as usize).div_ceil(8)];
+    write_pels_packed(line, width, &mut out).unwrap();
+    out
+}
+
+/// Options for decoding Group 3 images.
+///
+/// The `Default` implementation matches the behavior of `decode_g3`.
+#[derive(Copy, Clone, Debug)]
+pub struct G3DecodeOptions {
+    /// Width of the image.
+    /// 
+    /// If given, a line ends after `width` pixels, even if it is not followed by an EOL.
+    pub width: Option<u16>,
+
+    /// Each encoded line starts at a byte boundary. (`EncodedByteAlign` in PDF)
+    pub encoded_byte_align: bool,
+
+    /// Require a complete RTC at the end of the data,
+    /// and treat lines that do not match `width` as an error.
+    pub strict: bool,
+
+    /// The data starts with an EOL.
+    pub leading_eol: bool,
+
+    /// Lines are coded one- or two-dimensionally, as selected by the tag bit following each EOL. (Modified READ)
+    /// 
+    /// Requires `width` to be set, decoding fails with `FaxError::WidthMismatch` otherwise.
+    pub two_dimensional: bool,
+
+    /// Color of the first run of each line. T.4 requires `Color::White`.
+    /// 
+    /// The decoded lines still start with white, as for all other options.
+    pub start_color: Color,
+
+    /// Leave out the end of the line, which `decode_g3` reports as the last entry of each line.
+    /// 
+    /// The lines then only hold the color changes, like the ones of `decode_g4`.
+    pub omit_line_end: bool,
+
+    /// Coding scheme of the data, if it is not the one of the T.4 main body.
+    /// 
+    /// The scheme can not be told from the coded data, so it has to be taken from
+    /// the container format or the T.30 session. Schemes that can not be decoded
+    /// are rejected with `FaxError::UnsupportedCoding` before any data is read.
+    pub coding_scheme: Option<CodingScheme>,
+}
+impl Default for G3DecodeOptions {
+    fn default() -> Self {
+        G3DecodeOptions {
+            width: None,
+            encoded_byte_align: false,
+            strict: false,
+            leading_eol: true,
+            two_dimensional: false,
+            start_color: Color::White,
+            omit_line_end: false,
+            coding_scheme: None,
+        }
+    }
+}
+
+/// Skip fill bits and consume the following EOL.
+/// 
+/// Returns `false` if the next code is not an EOL.
+#[cfg(feature = "std")]
+fn skip_eol(reader: &mut impl BitReader) -> bool {
+    // no code has more than 11 leading zeros, so these have to be fill bits
+    while reader.peek(EOL.len) == Some(0) {
+        reader.consume(1);
+    }
+    if reader.peek(EOL.len) == Some(EOL.data) {
+        reader.consume(EOL.len);
+        true
+    } else {
+        false
+    }
+}
+
+/// Only (zero) padding is left in the input.
+#[cfg(feature = "std")]
+fn at_end(reader: &impl BitReader) -> bool {
+    if reader.peek(EOL.len).is_some() {
+        return false;
+    }
+    (1 .. EOL.len).rev().find_map(|n| reader.peek(n)).unwrap_or(0) == 0
+}
+
+/// Read the tag bit following an EOL in two-dimensional coding.
+/// 
+/// Returns `true` if the next line is coded one-dimensionally, which is also assumed at the end of data.
+#[cfg(feature = "std")]
+fn read_tag(reader: &mut impl BitReader) -> bool {
+    match reader.peek(1) {
+        Some(bit) => {
+            reader.consume(1);
+            bit == 1
+        }
+        None => true
+    }
+}
+
+/// Pass `line`, decoded with the first run in `start_color`, to `line_cb` as a line starting with white.
+#[cfg(feature = "std")]
+fn emit_line(line: &mut Vec<u16>, start_color: Color, line_cb: &mut impl FnMut(&[u16])) {
+    fn toggle_start(line: &mut Vec<u16>) {
+        if line.first() == Some(&0) {
+            line.remove(0);
+        } else {
+            line.insert(0, 0);
+        }
+    }
+    match start_color {
+        Color::White => line_cb(line),
+        Color::Black => {
+            toggle_start(line);
+            line_cb(line);
+            toggle_start(line);
+        }
+    }
+}
+
 /// Decode a Group 3 encoded image.
 /// 
 /// The callback `line_cb` is called for each decoded line.
 /// The argument is the list of positions of color change, starting with white.
+/// The last entry is the end of the line.
 /// 
 /// To obtain an iterator over the pixel colors, the `pels` function is provided.
-pub fn decode_g3(input: impl Iterator<Item=u8>, mut line_cb: impl FnMut(&[u16])) -> Option<()> {
+/// 
+/// # Examples
+/// ```rust
+/// use fax::decoder::{decode_g3, pels};
+/// // two lines of 8 pixels, each starting with an EOL, followed by the RTC
+/// let data = [
+///     0x00, 0x13, 0x57, 0x60, 0x02, 0xed, 0xc0,
+///     0x04, 0x00, 0x40, 0x04, 0x00, 0x40, 0x04, 0x00, 0x40,
+/// ];
+/// let mut black = 0;
+/// decode_g3(data.iter().cloned(), |line| {
+///     black += pels(line, 8).filter(|&c| c == fax::Color::Black).count();
+/// }).unwrap();
+/// assert_eq!(black, 8);
+/// ```
+#[cfg(feature = "std")]
+pub fn decode_g3(input: impl Iterator<Item=u8>, line_cb: impl FnMut(&[u16])) -> Option<()> {
+    decode_g3_with_options(input, &G3DecodeOptions::default(), line_cb).ok()
+}
+
+/// Decode a Group 3 encoded image using the given `options`.
+/// 
+/// Works like `decode_g3`, but reports the reason of a failure.
+/// Decoding stops at the end of data or at the RTC (a sequence of EOLs),
+/// whichever comes first. In strict mode, a missing or incomplete RTC is an error.
+#[cfg(feature = "std")]
+pub fn decode_g3_with_options(input: impl Iterator<Item=u8>, options: &G3DecodeOptions, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    if options.two_dimensional && options.width.is_none() {
+        return Err(FaxError::WidthMismatch);
+    }
+    if let Some(scheme) = options.coding_scheme {
+        return Err(FaxError::UnsupportedCoding(scheme));
+    }
     let mut reader = ByteReader::new(input);
+    let mut reference = vec![];
     let mut current = vec![];
-    reader.expect(EOL).unwrap();
+    let mut one_dimensional = true;
+
+    if options.leading_eol {
+        if !skip_eol(&mut reader) {
+            return Err(FaxError::InvalidCode);
+        }
+        if options.two_dimensional {
+            one_dimensional = read_tag(&mut reader);
+        }
+    }
     
-    'a: loop {
-        let mut a0 = 0;
-        let mut color = Color::White;
-        while let Some(p) = colored(color, &mut reader) {
-            a0 += p;
+    loop {
+        // in two-dimensional coding, the fill bits are in front of the EOL
+        if options.encoded_byte_align && !options.two_dimensional {
+            let fill = reader.bits_to_byte_boundary();
+            reader.consume(fill);
+        }
+        if at_end(&reader) {
+            if options.strict {
+                return Err(FaxError::UnexpectedEof);
+            }
+            break;
+        }
+
+        // an EOL at the start of a line is part of the RTC.
+        // The EOL ending the previous line (or the leading EOL) is the first of six.
+        let mut eols = 0;
+        while skip_eol(&mut reader) {
+            eols += 1;
+            if options.two_dimensional {
+                read_tag(&mut reader);
+            }
+        }
+        if eols > 0 {
+            if options.strict && eols < 5 {
+                return Err(FaxError::InvalidCode);
+            }
+            break;
+        }
+
+        let a0 = if one_dimensional {
+            let mut a0: u16 = 0;
+            let mut color = options.start_color;
+            loop {
+                if let Some(width) = options.width {
+                    if a0 >= width && !current.is_empty() {
+                        break;
+                    }
+                }
+                // fill bits or EOL
+                if reader.peek(8) == Some(0) {
+                    break;
+                }
+                let run = match colored(color, &mut reader) {
+                    Some(run) => run,
+                    None if reader.peek(1).is_none() => return Err(FaxError::UnexpectedEof),
+                    None => return Err(FaxError::InvalidCode),
+                };
+                a0 = a0.checked_add(run).ok_or(FaxError::InvalidCode)?;
+                current.push(a0);
+                color = !color;
+            }
+
+            // the last entry is the end of the line, not a color change.
+            // It is added again for the callback, unless `omit_line_end` is set.
+            current.pop();
+            a0
+        } else {
+            let width = options.width.unwrap();
+            if !decode_2d_line(&mut reader, &reference, &mut current, width, options.strict)? {
+                return Err(FaxError::InvalidCode);
+            }
+            width
+        };
+        // a line longer than the width is never valid, a shorter one only in strict mode
+        if let Some(width) = options.width {
+            if a0 > width || (options.strict && a0 != width) {
+                return Err(FaxError::WidthMismatch);
+            }
+        }
+        if options.omit_line_end || a0 == 0 {
+            emit_line(&mut current, options.start_color, &mut line_cb);
+        } else {
             current.push(a0);
-            color = !color;
+            emit_line(&mut current, options.start_color, &mut line_cb);
+            current.pop();
         }
-        reader.expect(EOL).unwrap();
-        line_cb(&current);
+        std::mem::swap(&mut reference, &mut current);
         current.clear();
 
-        for _ in 0 .. 6 {
-            if reader.peek(EOL.len) == Some(EOL.data) {
-                reader.consume(EOL.len);
-            } else {
-                continue 'a;
+        if skip_eol(&mut reader) {
+            if options.two_dimensional {
+                one_dimensional = read_tag(&mut reader);
             }
+        } else if (options.width.is_none() || options.two_dimensional) && !at_end(&reader) {
+            return Err(FaxError::InvalidCode);
+        }
+    }
+    Ok(())
+}
+
+/// Decode a one-dimensionally coded Group 3 image of the given `width`.
+/// 
+/// Works like `decode_g3`, but a line also ends after `width` pixels and the reason of a failure is reported.
+/// A line that is longer than `width` is reported as `FaxError::WidthMismatch`.
+#[cfg(feature = "std")]
+pub fn decode_g3_with_width(input: impl Iterator<Item=u8>, width: u16, line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let options = G3DecodeOptions {
+        width: Some(width),
+        ..G3DecodeOptions::default()
+    };
+    decode_g3_with_options(input, &options, line_cb)
+}
+
+/// Decode a two-dimensionally coded Group 3 image (Modified READ) of the given `width`.
+/// 
+/// Works like `decode_g3`, but each line may be coded relative to the previous one.
+#[cfg(feature = "std")]
+pub fn decode_g3_2d(input: impl Iterator<Item=u8>, width: u16, line_cb: impl FnMut(&[u16])) -> Option<()> {
+    let options = G3DecodeOptions {
+        width: Some(width),
+        two_dimensional: true,
+        ..G3DecodeOptions::default()
+    };
+    decode_g3_with_options(input, &options, line_cb).ok()
+}
+
+/// Options for decoding Group 4 images.
+///
+/// The `Default` implementation matches the behavior of `decode_g4`.
+#[derive(Copy, Clone, Debug)]
+pub struct G4DecodeOptions {
+    /// Treat extension mode codes as an error instead of skipping them.
+    /// 
+    /// If the data ends within a line, strict mode returns `FaxError::UnexpectedEof`.
+    /// Otherwise the line is reported as far as it was decoded, and decoding ends there without an error.
+    pub strict: bool,
+
+    /// Decode at most that many lines.
+    /// 
+    /// If `None`, data is decoded until the end-of-block marker (or end of data).
+    pub max_lines: Option<u32>,
+
+    /// Each encoded line starts at a byte boundary. (`EncodedByteAlign` in PDF)
+    pub encoded_byte_align: bool,
+
+    /// Color of the first run of each line. T.6 requires `Color::White`.
+    /// 
+    /// The decoded lines still start with white, as for all other options.
+    pub start_color: Color,
+}
+impl Default for G4DecodeOptions {
+    fn default() -> Self {
+        G4DecodeOptions {
+            strict: false,
+            max_lines: None,
+            encoded_byte_align: false,
+            start_color: Color::White,
         }
-        break;
     }
-    Some(())
 }
 
 /// Decode a Group 4 Image
@@ -92,93 +442,1337 @@ pub fn decode_g3(input: impl Iterator<Item=u8>, mut line_cb: impl FnMut(&[u16]))
 ///   otherwise data is decoded until the end-of-block marker (or end of data).
 /// 
 /// To obtain an iterator over the pixel colors, the `pels` function is provided.
-pub fn decode_g4(input: impl Iterator<Item=u8>, width: u16, height: Option<u16>, mut line_cb: impl FnMut(&[u16])) -> Option<()> {
+/// 
+/// # Examples
+/// ```rust
+/// use fax::{Color, decoder::{decode_g4, pels}};
+/// // two lines of 8 pixels, followed by the end-of-block marker
+/// let data = [0x26, 0xae, 0x18, 0x70, 0x01, 0x00, 0x10];
+/// let mut lines = vec![];
+/// decode_g4(data.iter().cloned(), 8, None, |line| lines.push(line.to_vec())).unwrap();
+/// assert_eq!(lines, [vec![0, 4], vec![2, 6]]);
+/// 
+/// // the first line starts with four black pixels
+/// let first: Vec<Color> = pels(&lines[0], 8).collect();
+/// assert_eq!(first[.. 5], [Color::Black, Color::Black, Color::Black, Color::Black, Color::White]);
+/// ```
+#[cfg(feature = "std")]
+pub fn decode_g4(input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, line_cb: impl FnMut(&[u16])) -> Option<()> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    decode_g4_with_options(input, width, &options, line_cb).ok()
+}
+
+/// Decode a Group 4 Image using the given `options`.
+/// 
+/// Works like `decode_g4`, but reports the reason of a failure.
+/// In strict mode, an extension code results in `FaxError::ExtensionMode`,
+/// otherwise it is skipped.
+#[cfg(feature = "std")]
+pub fn decode_g4_with_options(input: impl Iterator<Item=u8>, width: u16, options: &G4DecodeOptions, line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    decode_g4_inner(input, width, options, &mut LinePool::new(), &[], line_cb).map(|_| ())
+}
+
+/// Decode a Group 4 image into packed pixels, one bit per pixel with `1` for black.
+/// 
+/// Each row takes `(width + 7) / 8` bytes, as written by `write_pels_packed`.
+/// Returns the width, the number of decoded rows and the pixels.
+/// If `height` is given, decoding stops after that many rows, and `FaxError::UnexpectedEof` is returned if there are less.
+#[cfg(feature = "std")]
+pub fn g4_to_pixels(data: &[u8], width: u16, height: Option<u32>) -> Result<(u16, u32, Vec<u8>), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    let mut rows = PackedRows::new(width);
+    decode_g4_with_options(data.iter().cloned(), width, &options, |line| rows.push(line))?;
+    if let Some(height) = height {
+        if rows.height < height {
+            return Err(FaxError::UnexpectedEof);
+        }
+    }
+    Ok((width, rows.height, rows.pixels))
+}
+
+/// Decode a one-dimensionally coded Group 3 image into packed pixels, as `g4_to_pixels`.
+/// 
+/// The height is the number of lines before the RTC (or the end of the data).
+#[cfg(feature = "std")]
+pub fn g3_to_pixels(data: &[u8], width: u16) -> Result<(u16, u32, Vec<u8>), FaxError> {
+    let options = G3DecodeOptions {
+        width: Some(width),
+        omit_line_end: true,
+        .. G3DecodeOptions::default()
+    };
+    let mut rows = PackedRows::new(width);
+    decode_g3_with_options(data.iter().cloned(), &options, |line| rows.push(line))?;
+    Ok((width, rows.height, rows.pixels))
+}
+
+/// Collects decoded lines as packed pixels.
+#[cfg(feature = "std")]
+struct PackedRows {
+    width: u16,
+    height: u32,
+    pixels: Vec<u8>,
+}
+#[cfg(feature = "std")]
+impl PackedRows {
+    fn new(width: u16) -> Self {
+        PackedRows { width, height: 0, pixels: vec![] }
+    }
+    fn push(&mut self, line: &[u16]) {
+        let start = self.pixels.len();
+        self.pixels.resize(start + (self.width as usize).div_ceil(8), 0);
+        write_pels_packed(line, self.width, &mut self.pixels[start ..]).unwrap();
+        self.height += 1;
+    }
+}
+
+/// Decode only the first `n` lines of a Group 4 image, for example for a thumbnail.
+/// 
+/// Decoding stops after these lines, the rest of the data is not read.
+/// If the image has less lines, all of them are returned.
+#[cfg(feature = "std")]
+pub fn decode_g4_first_n_lines(data: &[u8], width: u16, n: u32) -> Result<Vec<Vec<u16>>, FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: Some(n),
+        .. G4DecodeOptions::default()
+    };
+    let mut lines = vec![];
+    decode_g4_with_options(data.iter().cloned(), width, &options, |line| lines.push(line.to_vec()))?;
+    Ok(lines)
+}
+
+/// Decode a Group 4 image, starting the output after the first `skip_lines` lines.
+/// 
+/// As each line is coded relative to the one above, the skipped lines still have to be decoded,
+/// but they are not passed to `line_cb`. After that, at most `max_lines` lines are decoded (if given).
+#[cfg(feature = "std")]
+pub fn decode_g4_skip(data: &[u8], width: u16, skip_lines: u32, max_lines: Option<u32>, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: max_lines.map(|n| skip_lines.saturating_add(n)),
+        .. G4DecodeOptions::default()
+    };
+    let mut skip = skip_lines;
+    decode_g4_with_options(data.iter().cloned(), width, &options, |line| {
+        if skip > 0 {
+            skip -= 1;
+        } else {
+            line_cb(line);
+        }
+    })
+}
+
+/// Decode a Group 4 Image and return the last decoded line.
+/// 
+/// Works like `decode_g4`, but the last line is returned (`None` if no line was decoded),
+/// so it can be used as the reference line of a subsequent encode or decode pass.
+#[cfg(feature = "std")]
+pub fn decode_g4_with_final_ref(input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, line_cb: impl FnMut(&[u16])) -> Result<Option<Vec<u16>>, FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    decode_g4_inner(input, width, &options, &mut LinePool::new(), &[], line_cb)
+}
+
+/// Decode a Group 4 Image, coded relative to `initial_reference` instead of an all-white line.
+/// 
+/// This is needed for formats where the first line of a page is coded relative to
+/// the last line of the previous page. Otherwise works like `decode_g4`.
+#[cfg(feature = "std")]
+pub fn decode_g4_with_reference(input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, initial_reference: &[u16], line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    decode_g4_inner(input, width, &options, &mut LinePool::new(), initial_reference, line_cb).map(|_| ())
+}
+
+/// Decode a Group 4 Image into an iterator over the pixels of each line.
+/// 
+/// The lines are decoded up front. If decoding fails, only the lines before the error are returned.
+/// Otherwise works like `decode_g4`.
+#[cfg(feature = "std")]
+pub fn decode_g4_pixels(input: impl Iterator<Item=u8>, width: u16, height: Option<u32>) -> impl Iterator<Item=impl Iterator<Item=Color>> {
+    let mut lines = vec![];
+    decode_g4(input, width, height, |line| lines.push(line.to_vec()));
+    lines.into_iter().map(move |line| {
+        let len = line.len();
+        pels_from(line.into_iter(), len, width)
+    })
+}
+
+/// Line buffers that are reused by `decode_g4_with_pool`.
+/// 
+/// Decoding needs two buffers (the current and the reference line).
+/// Using the same pool for several images (or strips) avoids allocating them again for each one.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct LinePool {
+    buffers: Vec<Vec<u16>>,
+}
+#[cfg(feature = "std")]
+impl LinePool {
+    /// Create an empty pool. Buffers are allocated when they are first needed.
+    pub fn new() -> Self {
+        LinePool::default()
+    }
+
+    /// Create a pool with two buffers for up to `capacity` color changes each.
+    pub fn with_capacity(capacity: usize) -> Self {
+        LinePool {
+            buffers: vec![Vec::with_capacity(capacity), Vec::with_capacity(capacity)],
+        }
+    }
+
+    fn take(&mut self) -> Vec<u16> {
+        self.buffers.pop().unwrap_or_default()
+    }
+
+    fn put(&mut self, mut buffer: Vec<u16>) {
+        buffer.clear();
+        self.buffers.push(buffer);
+    }
+}
+
+/// Decode a Group 4 Image using the line buffers of `pool`.
+/// 
+/// Works like `decode_g4`. The buffers are returned to the pool afterwards, unless decoding fails.
+#[cfg(feature = "std")]
+pub fn decode_g4_with_pool(pool: &mut LinePool, input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    if let Some(last) = decode_g4_inner(input, width, &options, pool, &[], line_cb)? {
+        pool.put(last);
+    }
+    Ok(())
+}
+
+/// Decode a Group 4 image like `decode_g4`, without allocating.
+/// 
+/// The current line and the line above are kept in the two halves of `buffer`.
+/// A line has at most `width` color changes, so `2 * width` entries are always enough.
+/// A line that does not fit is reported as `FaxError::BufferTooSmall`.
+/// This is available without the `std` feature.
+/// 
+/// # Examples
+/// ```rust
+/// use fax::decoder::decode_g4_fixed;
+/// // two lines of 8 pixels, followed by the end-of-block marker
+/// let data = [0x26, 0xae, 0x18, 0x70, 0x01, 0x00, 0x10];
+/// let mut buffer = [0; 2 * 8];
+/// let mut lines = 0;
+/// decode_g4_fixed(&mut buffer, data.iter().cloned(), 8, None, |_| lines += 1).unwrap();
+/// assert_eq!(lines, 2);
+/// ```
+pub fn decode_g4_fixed(buffer: &mut [u16], input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
     let mut reader = ByteReader::new(input);
-    let mut reference: Vec<u16> = vec![];
-    let mut current: Vec<u16> = vec![];
-
-    let limit = height.unwrap_or(u16::MAX);
-    'outer: for y in 0 .. limit {
-        let mut transitions = Transitions::new(&reference);
-        let mut a0 = 0;
-        let mut color = Color::White;
-        let mut start_of_row = true;
-        //println!("\n\nline {}", y);
+    let (mut reference, mut current) = buffer.split_at_mut(buffer.len() / 2);
+    let mut reference_len = 0;
+
+    let mut complete = true;
+    for _ in 0 .. height.unwrap_or(u32::MAX) {
+        let mut line = FixedLine { buf: current, len: 0 };
+        complete = match decode_2d_line(&mut reader, &reference[.. reference_len], &mut line, width, false) {
+            Ok(true) => true,
+            Ok(false) => break,
+            // report the damaged line as far as it was decoded
+            Err(FaxError::UnexpectedEof) => false,
+            Err(e) => return Err(e),
+        };
+
+        reference_len = line.len;
+        current = reference;
+        reference = line.buf;
+        line_cb(&reference[.. reference_len]);
+        if !complete {
+            break;
+        }
+    }
+    // after a damaged line, the data has already ended
+    if height.is_none() && complete {
+        reader.expect(EDFB_HALF).map_err(|e| match e {
+            Some(_) => FaxError::InvalidCode,
+            None => FaxError::UnexpectedEof,
+        })?;
+    }
+    Ok(())
+}
+
+/// Number of lines between two calls of the progress callback in `decode_g4_with_progress`.
+pub const PROGRESS_INTERVAL: u32 = 100;
+
+/// Decode a Group 4 Image and report the progress.
+/// 
+/// Works like `decode_g4`, but additionally calls `progress_cb` with the number of
+/// decoded lines every `PROGRESS_INTERVAL` lines.
+#[cfg(feature = "std")]
+pub fn decode_g4_with_progress(input: impl Iterator<Item=u8>, width: u16, height: Option<u32>, mut line_cb: impl FnMut(&[u16]), mut progress_cb: impl FnMut(u32)) -> Result<(), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: height,
+        .. G4DecodeOptions::default()
+    };
+    let mut lines = 0;
+    decode_g4_inner(input, width, &options, &mut LinePool::new(), &[], |line| {
+        line_cb(line);
+        lines += 1;
+        if lines % PROGRESS_INTERVAL == 0 {
+            progress_cb(lines);
+        }
+    }).map(|_| ())
+}
+
+/// Where the color changes of a line go while it is decoded.
+trait LineBuf {
+    fn push(&mut self, change: u16) -> Result<(), FaxError>;
+}
+#[cfg(feature = "std")]
+impl LineBuf for Vec<u16> {
+    fn push(&mut self, change: u16) -> Result<(), FaxError> {
+        Vec::push(self, change);
+        Ok(())
+    }
+}
+
+/// A line in a buffer of fixed size, which fails with `FaxError::BufferTooSmall` when it is full.
+struct FixedLine<'a> {
+    buf: &'a mut [u16],
+    len: usize,
+}
+impl LineBuf for FixedLine<'_> {
+    fn push(&mut self, change: u16) -> Result<(), FaxError> {
+        *self.buf.get_mut(self.len).ok_or(FaxError::BufferTooSmall)? = change;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Decode one two-dimensionally coded line of `width` pixels relative to `reference` into `current`.
+/// 
+/// Returns `Ok(false)` if the data ends or an EOL is found instead of a line.
+fn decode_2d_line(reader: &mut impl BitReader, reference: &[u16], current: &mut impl LineBuf, width: u16, strict: bool) -> Result<bool, FaxError> {
+    decode_2d_line_events(reader, reference, current, width, strict, &mut |_| ())
+}
+
+/// `decode_2d_line`, reporting the mode codes and color changes to `event`.
+fn decode_2d_line_events(reader: &mut impl BitReader, reference: &[u16], current: &mut impl LineBuf, width: u16, strict: bool, event: &mut impl FnMut(G4Event)) -> Result<bool, FaxError> {
+    let mut transitions = Transitions::new(reference);
+    let mut a0 = 0;
+    let mut color = Color::White;
+    let mut start_of_row = true;
+
+    loop {
+        //reader.print_peek();
+        let mode = match mode::decode(reader) {
+            Some(mode) => mode,
+            None if start_of_row => return Ok(false),
+            // the data ends within the line, `current` holds the part decoded so far
+            None => return Err(FaxError::UnexpectedEof),
+        };
+        event(G4Event::ModeCode(mode));
+        //println!("  {:?}, color={:?}, a0={}", mode, color, a0);
         
+        match mode {
+            Mode::Pass => {
+                if start_of_row && color == Color::White {
+                    transitions.pos += 1;
+                } else {
+                    transitions.next_color(a0, !color, false).ok_or(FaxError::InvalidCode)?;
+                }
+                //println!("b1={}", b1);
+                // without b2 on the reference line, the imaginary change at `width` is used
+                a0 = transitions.next().unwrap_or(width);
+            }
+            Mode::Vertical(delta) => {
+                let b1 = transitions.next_color(a0, !color, start_of_row).unwrap_or(width);
+                let a1 = (b1 as i16 + delta as i16) as u16;
+                if a1 >= width {
+                    break;
+                }
+                //println!("transition to {:?} at {}", !color, a1);
+                current.push(a1)?;
+                event(G4Event::ColorTransition { position: a1, color: !color });
+                color = !color;
+                a0 = a1;
+                if delta < 0 {
+                    transitions.seek_back(a0);
+                }
+            }
+            Mode::Horizontal => {
+                let a0a1 = run(color, reader)?;
+                let a1a2 = run(!color, reader)?;
+                // the runs can add up to more than fits a `u16`
+                let a1 = a0.checked_add(a0a1).ok_or(FaxError::InvalidCode)?;
+                let a2 = a1.checked_add(a1a2).ok_or(FaxError::InvalidCode)?;
+                //println!("a0a1={}, a1a2={}, a1={}, a2={}", a0a1, a1a2, a1, a2);
+                
+                if a1 >= width {
+                    break;
+                }
+                current.push(a1)?;
+                event(G4Event::ColorTransition { position: a1, color: !color });
+                if a2 >= width {
+                    break;
+                }
+                current.push(a2)?;
+                event(G4Event::ColorTransition { position: a2, color });
+                a0 = a2;
+            }
+            Mode::Extension => {
+                if strict {
+                    return Err(FaxError::ExtensionMode);
+                }
+                // skip the 3 bits selecting the extension
+                reader.peek(3).ok_or(FaxError::UnexpectedEof)?;
+                reader.consume(3);
+                continue;
+            }
+            Mode::EOF => return Ok(false),
+        }
+        start_of_row = false;
+
+        if a0 >= width {
+            break;
+        }
+    }
+    //println!("{:?}", current);
+    Ok(true)
+}
+
+/// An element of a Group 4 stream, as reported by `inspect_g4`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum G4Event {
+    /// Decoding of the line with the given index starts.
+    LineStart(u32),
+    /// A mode code was read.
+    ModeCode(Mode),
+    /// The color changes to `color` at `position`.
+    ColorTransition { position: u16, color: Color },
+    /// The line with the given index is complete.
+    LineEnd(u32),
+}
+
+/// Decode a Group 4 stream into the sequence of codes and color changes, for debugging.
+/// 
+/// Decoding stops at the end-of-block marker, the end of data, or the first error.
+/// An incomplete line has a `LineStart`, but no `LineEnd` event.
+#[cfg(feature = "std")]
+pub fn inspect_g4(data: &[u8], width: u16) -> Vec<G4Event> {
+    let mut reader = ByteReader::from_slice(data);
+    let mut reference = vec![];
+    let mut current = vec![];
+    let mut events = vec![];
+    for y in 0 .. {
+        events.push(G4Event::LineStart(y));
+        match decode_2d_line_events(&mut reader, &reference, &mut current, width, false, &mut |e| events.push(e)) {
+            Ok(true) => events.push(G4Event::LineEnd(y)),
+            _ => break,
+        }
+        std::mem::swap(&mut reference, &mut current);
+        current.clear();
+    }
+    events
+}
+
+/// An element of a one-dimensionally coded Group 3 stream, as reported by `inspect_g3`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum G3Event {
+    /// An EOL, including fill bits in front of it.
+    Eol,
+    /// A terminating or (if `make_up` is set) make-up code.
+    RunLength { color: Color, run: u16, make_up: bool },
+    /// The sixth EOL in a row, following its `Eol` event.
+    Rtc,
+    /// No code starts at this bit.
+    InvalidCode { bit_position: u64 },
+}
+
+/// Reads bits from a slice, at a position that can be set.
+#[cfg(feature = "std")]
+struct SliceBits<'a> {
+    data: &'a [u8],
+    pos: u64,
+}
+#[cfg(feature = "std")]
+impl BitReader for SliceBits<'_> {
+    fn peek(&self, bits: u8) -> Option<u16> {
+        if self.pos + bits as u64 > self.data.len() as u64 * 8 {
+            return None;
+        }
+        let mut out = 0;
+        for i in self.pos .. self.pos + bits as u64 {
+            let bit = self.data[(i / 8) as usize] >> (7 - i % 8) & 1;
+            out = out << 1 | bit as u16;
+        }
+        Some(out)
+    }
+    fn consume(&mut self, bits: u8) {
+        self.pos += bits as u64;
+    }
+    fn bits_to_byte_boundary(&self) -> u8 {
+        ((8 - self.pos % 8) % 8) as u8
+    }
+}
+
+/// Decode a one-dimensionally coded Group 3 stream into the sequence of codes, for debugging.
+/// 
+/// Each line starts with a white run. Where no code can be read, an `InvalidCode` event is reported
+/// and scanning continues at the next EOL (searched for from the following bit), so the lines after a corruption can still be inspected.
+#[cfg(feature = "std")]
+pub fn inspect_g3(data: &[u8]) -> Vec<G3Event> {
+    let mut reader = SliceBits { data, pos: 0 };
+    let mut events = vec![];
+    let mut color = Color::White;
+    let mut eols = 0;
+    while !at_end(&reader) {
+        if skip_eol(&mut reader) {
+            events.push(G3Event::Eol);
+            color = Color::White;
+            eols += 1;
+            if eols == 6 {
+                events.push(G3Event::Rtc);
+                eols = 0;
+            }
+            continue;
+        }
+        eols = 0;
+        let start = reader.pos;
+        let code = match color {
+            Color::White => white::decode(&mut reader),
+            Color::Black => black::decode(&mut reader),
+        };
+        match code {
+            Some(run) => {
+                let make_up = run >= 64;
+                events.push(G3Event::RunLength { color, run, make_up });
+                if !make_up {
+                    color = !color;
+                }
+            }
+            None => {
+                events.push(G3Event::InvalidCode { bit_position: start });
+                // look for an EOL from the next bit on, zeros in it can be taken for other codes
+                reader.pos = start + 1;
+                while matches!(reader.peek(EOL.len), Some(bits) if bits != EOL.data) {
+                    reader.pos += 1;
+                }
+            }
+        }
+    }
+    events
+}
+
+/// Decode lines, starting with the given `initial_reference` line, using buffers from `pool`.
+/// 
+/// Returns the last decoded line, if any.
+#[cfg(feature = "std")]
+fn decode_g4_inner(input: impl Iterator<Item=u8>, width: u16, options: &G4DecodeOptions, pool: &mut LinePool, initial_reference: &[u16], mut line_cb: impl FnMut(&[u16])) -> Result<Option<Vec<u16>>, FaxError> {
+    let mut reader = ByteReader::new(input);
+    let mut reference = pool.take();
+    reference.extend_from_slice(initial_reference);
+    let mut current = pool.take();
+    let mut lines = 0;
+
+    let limit = options.max_lines.unwrap_or(u32::MAX);
+    let mut complete = true;
+    for _ in 0 .. limit {
+        if options.encoded_byte_align {
+            let fill = reader.bits_to_byte_boundary();
+            reader.consume(fill);
+        }
+        complete = match decode_2d_line(&mut reader, &reference, &mut current, width, options.strict) {
+            Ok(true) => true,
+            Ok(false) => break,
+            // report the damaged line as far as it was decoded
+            Err(FaxError::UnexpectedEof) if !options.strict => false,
+            Err(e) => return Err(e),
+        };
+
+        emit_line(&mut current, options.start_color, &mut line_cb);
+        lines += 1;
+        std::mem::swap(&mut reference, &mut current);
+        current.clear();
+        if !complete {
+            break;
+        }
+    }
+    // after a damaged line, the data has already ended
+    if options.max_lines.is_none() && complete {
+        reader.expect(EDFB_HALF).map_err(|e| match e {
+            Some(_) => FaxError::InvalidCode,
+            None => FaxError::UnexpectedEof,
+        })?;
+    }
+    pool.put(current);
+
+    if lines > 0 {
+        Ok(Some(reference))
+    } else {
+        pool.put(reference);
+        Ok(None)
+    }
+}
+
+/// Counts the bits consumed from `reader`.
+#[cfg(feature = "std")]
+struct Counting<R> {
+    reader: R,
+    consumed: usize,
+}
+#[cfg(feature = "std")]
+impl<R: BitReader> BitReader for Counting<R> {
+    fn peek(&self, bits: u8) -> Option<u16> {
+        self.reader.peek(bits)
+    }
+    fn consume(&mut self, bits: u8) {
+        self.consumed += bits as usize;
+        self.reader.consume(bits);
+    }
+    fn bits_to_byte_boundary(&self) -> u8 {
+        self.reader.bits_to_byte_boundary()
+    }
+}
+
+/// Build an index of the lines where decoding of a Group 4 image can start, for random access.
+/// 
+/// Decoding can only start at the first line or at a line below an all-white line, as that serves as reference.
+/// Of these lines, the entries are at least `key_frame_interval` lines apart.
+/// Each entry holds the line number and the position of the line in `data` in bits, as lines do not start at byte boundaries.
+/// If the data is corrupt, the index covers the lines before the error.
+#[cfg(feature = "std")]
+pub fn build_g4_index(data: &[u8], width: u16, key_frame_interval: u32) -> Vec<(u32, u64)> {
+    let mut reader = Counting { reader: ByteReader::from_slice(data), consumed: 0 };
+    let mut reference = vec![];
+    let mut current = vec![];
+    let mut index: Vec<(u32, u64)> = vec![];
+    for y in 0 .. {
+        let due = match index.last() {
+            Some(&(last, _)) => y - last >= key_frame_interval,
+            None => true,
+        };
+        let pos = reader.consumed as u64;
+        match decode_2d_line(&mut reader, &reference, &mut current, width, false) {
+            Ok(true) => {}
+            _ => break,
+        }
+        if reference.is_empty() && due {
+            index.push((y, pos));
+        }
+        std::mem::swap(&mut reference, &mut current);
+        current.clear();
+    }
+    index
+}
+
+/// Decode a Group 4 image from line `start_line` on, using an `index` built by `build_g4_index`.
+/// 
+/// Decoding starts at the last entry of the index up to `start_line`,
+/// the lines before `start_line` are not passed to `line_cb`.
+#[cfg(feature = "std")]
+pub fn decode_g4_from_index(data: &[u8], index: &[(u32, u64)], width: u16, start_line: u32, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let &(first, pos) = index.iter().rev().find(|&&(line, _)| line <= start_line).unwrap_or(&(0, 0));
+    let mut reader = ByteReader::from_slice(data.get((pos / 8) as usize ..).ok_or(FaxError::UnexpectedEof)?);
+    reader.peek((pos % 8) as u8).ok_or(FaxError::UnexpectedEof)?;
+    reader.consume((pos % 8) as u8);
+
+    let mut reference = vec![];
+    let mut current = vec![];
+    for y in first .. {
+        if !decode_2d_line(&mut reader, &reference, &mut current, width, false)? {
+            break;
+        }
+        if y >= start_line {
+            line_cb(&current);
+        }
+        std::mem::swap(&mut reference, &mut current);
+        current.clear();
+    }
+    reader.expect(EDFB_HALF).map_err(|e| match e {
+        Some(_) => FaxError::InvalidCode,
+        None => FaxError::UnexpectedEof,
+    })
+}
+
+/// Group 4 decoder reading from a `tokio::io::AsyncRead`.
+/// 
+/// Data is read in chunks until the buffered data holds a complete line,
+/// which is then decoded like in `decode_g4`.
+#[cfg(feature = "tokio")]
+pub struct AsyncG4Decoder<R> {
+    reader: R,
+    width: u16,
+    buf: Vec<u8>,
+    /// bit position of the next line in `buf`
+    pos: usize,
+    eof: bool,
+    done: bool,
+    reference: Vec<u16>,
+    current: Vec<u16>,
+}
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncRead + Unpin> AsyncG4Decoder<R> {
+    pub fn new(reader: R, width: u16) -> Self {
+        AsyncG4Decoder {
+            reader,
+            width,
+            buf: vec![],
+            pos: 0,
+            eof: false,
+            done: false,
+            reference: vec![],
+            current: vec![],
+        }
+    }
+
+    /// Decode the next line.
+    /// 
+    /// Returns `None` at the end of the image. After an error, no more lines are returned.
+    pub async fn next_line(&mut self) -> Option<Result<Vec<u16>, FaxError>> {
+        use tokio::io::AsyncReadExt;
+        if self.done {
+            return None;
+        }
         loop {
-            //reader.print_peek();
-            let mode = match mode::decode(&mut reader) {
-                Some(mode) => mode,
-                None => break 'outer,
-            };
-            //println!("  {:?}, color={:?}, a0={}", mode, color, a0);
-            
-            match mode {
-                Mode::Pass => {
-                    if start_of_row && color == Color::White {
-                        transitions.pos += 1;
-                    } else {
-                        transitions.next_color(a0, !color, false)?;
-                    }
-                    //println!("b1={}", b1);
-                    if let Some(b2) = transitions.next() {
-                        //println!("b2={}", b2);
-                        a0 = b2;
-                    }
+            let start = self.pos / 8;
+            self.buf.drain(.. start);
+            self.pos -= 8 * start;
+
+            let mut reader = Counting { reader: ByteReader::from_slice(&self.buf), consumed: 0 };
+            reader.consume(self.pos as u8);
+            self.current.clear();
+            let result = decode_2d_line(&mut reader, &self.reference, &mut self.current, self.width, false);
+
+            // a line that is cut off could look like an error, so only trust that at the end of data
+            match result {
+                Ok(true) => {
+                    self.pos = reader.consumed;
+                    std::mem::swap(&mut self.reference, &mut self.current);
+                    return Some(Ok(self.reference.clone()));
                 }
-                Mode::Vertical(delta) => {
-                    let b1 = transitions.next_color(a0, !color, start_of_row).unwrap_or(width);
-                    let a1 = (b1 as i16 + delta as i16) as u16;
-                    if a1 >= width {
-                        break;
+                // the first half of the EOFB, so the image ends here once the second half is there as well
+                Ok(false) if reader.consumed == self.pos + EDFB_HALF.len as usize => match reader.expect(EDFB_HALF) {
+                    Ok(()) => {
+                        self.done = true;
+                        return None;
                     }
-                    //println!("transition to {:?} at {}", !color, a1);
-                    current.push(a1);
-                    color = !color;
-                    a0 = a1;
-                    if delta < 0 {
-                        transitions.seek_back(a0);
+                    Err(None) if !self.eof => {}
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(if e.is_some() { FaxError::InvalidCode } else { FaxError::UnexpectedEof }));
                     }
                 }
-                Mode::Horizontal => {
-                    let a0a1 = colored(color, &mut reader)?;
-                    let a1a2 = colored(!color, &mut reader)?;
-                    let a1 = a0 + a0a1;
-                    let a2 = a1 + a1a2;
-                    //println!("a0a1={}, a1a2={}, a1={}, a2={}", a0a1, a1a2, a1, a2);
-                    
-                    current.push(a1);
-                    if a2 >= width {
-                        break;
-                    }
-                    current.push(a2);
-                    a0 = a2;
+                _ if !self.eof => {}
+                Ok(false) => {
+                    self.done = true;
+                    return None;
                 }
-                Mode::Extension => {
-                    let xxx = reader.peek(3)?;
-                    println!("extension: {:03b}", xxx);
-                    reader.consume(3);
-                    println!("{:?}", current);
-                    break 'outer;
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
                 }
-                Mode::EOF => break 'outer,
             }
-            start_of_row = false;
 
-            if a0 >= width {
-                break;
+            let mut chunk = [0; 4096];
+            match self.reader.read(&mut chunk).await {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[.. n]),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
             }
         }
-        //println!("{:?}", current);
+    }
+}
 
-        line_cb(&current);
-        std::mem::swap(&mut reference, &mut current);
-        current.clear();
+/// Compression schemes told apart by `detect_compression`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaxCompression {
+    /// Group 3, one-dimensional (Modified Huffman)
+    G3,
+    /// Group 4 (Modified Modified READ)
+    G4,
+    /// Group 3, two-dimensional (Modified READ)
+    G3TwoDimensional,
+}
+
+/// Positions of the last bit of each EOL in the bits of `data`.
+#[cfg(feature = "std")]
+fn eol_ends(data: &[u8]) -> Vec<usize> {
+    let mut zeros = 0;
+    let mut ends = vec![];
+    for (i, bit) in ByteReader::from_slice(data).into_bits().enumerate() {
+        if !bit {
+            zeros += 1;
+        } else {
+            if zeros >= EOL.len - 1 {
+                ends.push(i);
+            }
+            zeros = 0;
+        }
     }
-    if height.is_none() {
-        reader.expect(EDFB_HALF).ok()?;
+    ends
+}
+
+/// Number of lines `detect_compression` decodes to confirm a guess.
+#[cfg(feature = "std")]
+const DETECT_LINES: u32 = 4;
+
+/// The widths of T.4 (A4, B4 and A3 at 8 pels/mm), which `detect_compression` tries for the codings that need one.
+#[cfg(feature = "std")]
+const FAX_WIDTHS: [u16; 3] = [1728, 2048, 2432];
+
+/// Returns `true` if the first `DETECT_LINES` lines of `data` (or all of them, if there are less)
+/// decode without an error.
+#[cfg(feature = "std")]
+fn decodes_first_lines(data: &[u8], compression: FaxCompression, width: u16) -> bool {
+    let lines = std::cell::Cell::new(0);
+    // stop feeding input once enough lines are decoded, whatever happens to the line after them
+    let input = data.iter().cloned().take_while(|_| lines.get() < DETECT_LINES);
+    let count = |_: &[u16]| lines.set(lines.get() + 1);
+    let result = match compression {
+        FaxCompression::G3 => {
+            let options = G3DecodeOptions { strict: true, .. G3DecodeOptions::default() };
+            decode_g3_with_options(input, &options, count)
+        }
+        FaxCompression::G3TwoDimensional => {
+            let options = G3DecodeOptions { width: Some(width), two_dimensional: true, strict: true, .. G3DecodeOptions::default() };
+            decode_g3_with_options(input, &options, count)
+        }
+        FaxCompression::G4 => {
+            let options = G4DecodeOptions { strict: true, .. G4DecodeOptions::default() };
+            decode_g4_with_options(input, width, &options, count)
+        }
+    };
+    lines.get() >= DETECT_LINES || (result.is_ok() && lines.get() > 0)
+}
+
+/// Guess the compression of `data`. This is inherently a best-effort function.
+/// 
+/// - Group 3 data starts with an EOL and ends with the RTC, six EOLs which are followed
+///   by a tag bit each when two-dimensional coding is used.
+/// - Group 4 data ends with the EOFB, two EOLs, and does not start with an EOL.
+/// 
+/// The coding these markers point to is tried first, then the others. The first one that decodes
+/// the first few lines without an error is returned, or `None` if none does.
+/// Two-dimensional Group 3 and Group 4 need the width, for which the widths of T.4 are tried
+/// (see `detect_compression_with_width` for other widths).
+#[cfg(feature = "std")]
+pub fn detect_compression(data: &[u8]) -> Option<FaxCompression> {
+    detect(data, &FAX_WIDTHS)
+}
+
+/// Like `detect_compression`, for images that are `width` pixels wide.
+#[cfg(feature = "std")]
+pub fn detect_compression_with_width(data: &[u8], width: u16) -> Option<FaxCompression> {
+    detect(data, &[width])
+}
+
+#[cfg(feature = "std")]
+fn detect(data: &[u8], widths: &[u16]) -> Option<FaxCompression> {
+    // look at the first few bytes and the last few bytes only
+    const SCAN: usize = 32;
+    let head = eol_ends(&data[.. data.len().min(SCAN)]);
+    let tail = eol_ends(&data[data.len().saturating_sub(SCAN) ..]);
+    let starts_with_eol = matches!(head.first(), Some(&end) if end < 2 * EOL.len as usize + 8);
+
+    // count the EOLs at the end that directly follow each other (or with one tag bit in between)
+    let chain = |step: usize| tail.windows(2).rev().take_while(|w| w[1] - w[0] == step).count() + 1;
+    let chain_1d = chain(EOL.len as usize);
+    let chain_2d = chain(EOL.len as usize + 1);
+
+    let mut candidates = match (tail.is_empty(), starts_with_eol) {
+        (false, _) if chain_2d >= 6 => vec![FaxCompression::G3TwoDimensional],
+        (false, _) if chain_1d >= 6 => vec![FaxCompression::G3],
+        (false, false) if chain_1d == 2 => vec![FaxCompression::G4],
+        _ => vec![],
+    };
+    for compression in [FaxCompression::G3, FaxCompression::G3TwoDimensional, FaxCompression::G4] {
+        if !candidates.contains(&compression) {
+            candidates.push(compression);
+        }
+    }
+    candidates.into_iter().find(|&compression| match compression {
+        // the end of the line gives the width
+        FaxCompression::G3 => decodes_first_lines(data, compression, 0),
+        _ => widths.iter().any(|&width| decodes_first_lines(data, compression, width)),
+    })
+}
+
+/// Alias of `decode_g3` using the name of the coding scheme (Modified Huffman).
+#[cfg(feature = "std")]
+pub use self::decode_g3 as decode_mh;
+/// Alias of `decode_g3_2d` using the name of the coding scheme (Modified READ).
+#[cfg(feature = "std")]
+pub use self::decode_g3_2d as decode_mr;
+/// Alias of `decode_g4` using the name of the coding scheme (Modified Modified READ).
+#[cfg(feature = "std")]
+pub use self::decode_g4 as decode_mmr;
+
+/// The coding schemes of T.4 and T.6.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaxCoding {
+    /// Modified Huffman, one-dimensional Group 3
+    Mh,
+    /// Modified READ, two-dimensional Group 3
+    Mr,
+    /// Modified Modified READ, Group 4
+    Mmr,
+}
+#[cfg(feature = "std")]
+impl FaxCoding {
+    /// Decode an image of the given `width` with the matching decoder.
+    /// 
+    /// See `decode_g3_with_options` and `decode_g4_with_options` for the details.
+    /// For all codings, the lines only hold the color changes (see `G3DecodeOptions::omit_line_end`).
+    pub fn decode(self, input: impl Iterator<Item=u8>, width: u16, line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+        let g3 = |two_dimensional| G3DecodeOptions {
+            width: Some(width),
+            two_dimensional,
+            omit_line_end: true,
+            ..G3DecodeOptions::default()
+        };
+        match self {
+            FaxCoding::Mh => decode_g3_with_options(input, &g3(false), line_cb),
+            FaxCoding::Mr => decode_g3_with_options(input, &g3(true), line_cb),
+            FaxCoding::Mmr => decode_g4_with_options(input, width, &G4DecodeOptions::default(), line_cb),
+        }
+    }
+}
+impl From<FaxCompression> for FaxCoding {
+    fn from(compression: FaxCompression) -> Self {
+        match compression {
+            FaxCompression::G3 => FaxCoding::Mh,
+            FaxCompression::G3TwoDimensional => FaxCoding::Mr,
+            FaxCompression::G4 => FaxCoding::Mmr,
+        }
     }
-    reader.print_remaining();
+}
+
+#[test]
+fn test_pels_u8() {
+    assert_eq!(pels_u8(&[1, 2], 4).collect::<Vec<_>>(), [0xFF, 0x00, 0xFF, 0xFF]);
+    assert_eq!(pels_bool(&[0, 3], 4).collect::<Vec<_>>(), [true, true, true, false]);
+}
+
+#[test]
+fn test_pels_packed() {
+    assert_eq!(pels_packed(&[1, 3, 7], 10), [0b0110_0001, 0b1100_0000]);
+    assert_eq!(write_pels_packed(&[], 10, &mut [0]), Err(FaxError::BufferTooSmall));
+}
+
+#[test]
+fn test_detect_compression() {
+    use crate::{VecWriter, encoder::{encode_g4, G3Encoder}};
+    assert_eq!(detect_compression_with_width(include_bytes!("../stream/6"), 264), Some(FaxCompression::G4));
+    // EOL, white 1728, EOL, and five more EOLs
+    let g3 = [0x00, 0x14, 0xD9, 0xA8, 0x00, 0x10, 0x01, 0x00, 0x10, 0x01, 0x00, 0x10, 0x01, 0x00, 0x10];
+    assert_eq!(detect_compression(&g3), Some(FaxCompression::G3));
+
+    let lines: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![100 + y, 200, 1000 - y, 1700]).collect();
+    let mut encoder = G3Encoder::new(VecWriter::new()).with_k_factor(2);
+    for line in &lines {
+        encoder.encode_transitions(line, 1728);
+    }
+    let g3_2d = encoder.finish().finish();
+    assert_eq!(detect_compression(&g3_2d), Some(FaxCompression::G3TwoDimensional));
+    let g4 = encode_g4(lines.iter().map(|line| line.as_slice()), 1728);
+    assert_eq!(detect_compression(&g4), Some(FaxCompression::G4));
+    // no EOL for Group 3 and no valid mode code for Group 4
+    assert_eq!(detect_compression(&[0; 16]), None);
+}
+
+#[test]
+fn test_decode_mr() {
+    use crate::{BitWriter, VecWriter, Bits};
+    let mut writer = VecWriter::new();
+    // EOL + 1, white 1728
+    writer.write(EOL);
+    writer.write(Bits { data: 1, len: 1 });
+    writer.write(Bits { data: 0b010011011, len: 9 });
+    writer.write(Bits { data: 0b00110101, len: 8 });
+    // EOL + 0, V0
+    writer.write(EOL);
+    writer.write(Bits { data: 0, len: 1 });
+    writer.write(Bits { data: 1, len: 1 });
+    for _ in 0 .. 6 {
+        writer.write(EOL);
+        writer.write(Bits { data: 1, len: 1 });
+    }
+    let data = writer.finish();
+
+    let mut lines = 0;
+    FaxCoding::Mr.decode(data.iter().cloned(), 1728, |line| {
+        assert_eq!(line, &[]);
+        lines += 1;
+    }).unwrap();
+    assert_eq!(lines, 2);
+}
+
+#[test]
+fn test_line_pool() {
+    let data = include_bytes!("../stream/6");
+    let mut pool = LinePool::with_capacity(64);
+    let mut lines = 0;
+    decode_g4_with_pool(&mut pool, data.iter().cloned(), 264, None, |_| lines += 1).unwrap();
+    assert!(lines > 0);
+    assert_eq!(pool.buffers.len(), 2);
+}
+
+#[test]
+fn test_unsupported_coding_scheme() {
+    let data = crate::encoder::encode_g3([&[10u16, 20][..]].iter().cloned(), 100);
+    let options = G3DecodeOptions {
+        coding_scheme: Some(CodingScheme::T4AnnexC),
+        .. G3DecodeOptions::default()
+    };
+    let mut lines = 0;
+    let result = decode_g3_with_options(data.iter().cloned(), &options, |_| lines += 1);
+    assert_eq!(result, Err(FaxError::UnsupportedCoding(CodingScheme::T4AnnexC)));
+    assert_eq!(lines, 0);
+}
+
+#[test]
+fn test_g3_2d_without_width() {
+    let data = crate::encoder::encode_g3([&[10u16, 20][..]].iter().cloned(), 100);
+    let options = G3DecodeOptions { two_dimensional: true, .. G3DecodeOptions::default() };
+    let mut lines = 0;
+    let result = decode_g3_with_options(data.iter().cloned(), &options, |_| lines += 1);
+    assert_eq!(result, Err(FaxError::WidthMismatch));
+    assert_eq!(lines, 0);
+}
+
+#[test]
+fn test_start_color() {
+    use crate::{BitWriter, VecWriter, Bits};
+    // EOL, black 10, white 20, black 70, EOL
+    let mut writer = VecWriter::new();
+    writer.write(EOL);
+    writer.write(Bits { data: 0b0000100, len: 7 });
+    writer.write(Bits { data: 0b0001000, len: 7 });
+    writer.write(Bits { data: 0b0000001111, len: 10 });
+    writer.write(Bits { data: 0b0010, len: 4 });
+    writer.write(EOL);
+    let data = writer.finish();
+
+    let options = G3DecodeOptions {
+        width: Some(100),
+        start_color: Color::Black,
+        .. G3DecodeOptions::default()
+    };
+    let mut lines = vec![];
+    decode_g3_with_options(data.iter().cloned(), &options, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, [[0, 10, 30, 100]]);
+}
+
+#[test]
+fn test_decode_g4_pixels() {
+    let data = include_bytes!("../stream/6");
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), 264, None, |line| lines.push(pels(line, 264).collect::<Vec<_>>()));
+    let pixels: Vec<Vec<Color>> = decode_g4_pixels(data.iter().cloned(), 264, None).map(|line| line.collect()).collect();
+    assert_eq!(pixels, lines);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_decoder() {
+    let data = include_bytes!("../stream/6");
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), 264, None, |line| lines.push(line.to_vec()));
+
+    /// Yields at most 3 bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+    impl tokio::io::AsyncRead for Trickle<'_> {
+        fn poll_read(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context, buf: &mut tokio::io::ReadBuf) -> std::task::Poll<std::io::Result<()>> {
+            let n = self.0.len().min(3).min(buf.remaining());
+            buf.put_slice(&self.0[.. n]);
+            self.0 = &self.0[n ..];
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    let mut decoder = AsyncG4Decoder::new(Trickle(data), 264);
+    let mut async_lines = vec![];
+    while let Some(line) = decoder.next_line().await {
+        async_lines.push(line.unwrap());
+    }
+    assert_eq!(async_lines, lines);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_decoder_stops_at_eofb() {
+    use crate::encoder::encode_g4;
+    let lines = [vec![10, 20], vec![12, 20]];
+    let data = encode_g4(lines.iter().map(|line| line.as_slice()), 100);
+
+    /// Yields one chunk per read and no end of data, like a connection that stays open.
+    struct Open<'a>(Vec<&'a [u8]>);
+    impl tokio::io::AsyncRead for Open<'_> {
+        fn poll_read(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context, buf: &mut tokio::io::ReadBuf) -> std::task::Poll<std::io::Result<()>> {
+            assert!(!self.0.is_empty(), "read after the EOFB");
+            buf.put_slice(self.0.remove(0));
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    let mut decoder = AsyncG4Decoder::new(Open(vec![&data]), 100);
+    assert_eq!(decoder.next_line().await, Some(Ok(lines[0].clone())));
+    assert_eq!(decoder.next_line().await, Some(Ok(lines[1].clone())));
+    assert_eq!(decoder.next_line().await, None);
+
+    // with the EOFB cut off, the decoder waits for the rest of it
+    let (head, tail) = data.split_at(data.len() - 1);
+    let mut decoder = AsyncG4Decoder::new(Open(vec![head, tail]), 100);
+    decoder.next_line().await;
+    decoder.next_line().await;
+    assert_eq!(decoder.next_line().await, None);
+}
+
+#[test]
+fn test_g4_to_pixels() {
+    let pixels = [0xF0, 0x0F, 0x00];
+    let data = crate::encoder::pixels_to_g4(8, 3, &pixels).unwrap();
+    assert_eq!(g4_to_pixels(&data, 8, None), Ok((8, 3, pixels.to_vec())));
+    assert_eq!(g4_to_pixels(&data, 8, Some(2)), Ok((8, 2, pixels[.. 2].to_vec())));
+    assert_eq!(g4_to_pixels(&data, 8, Some(4)), Err(FaxError::UnexpectedEof));
+}
+
+#[test]
+fn test_g3_to_pixels() {
+    let lines = [vec![0, 4], vec![4], vec![]];
+    let data = crate::encoder::encode_g3(lines.iter().map(|line| line.as_slice()), 8);
+    assert_eq!(g3_to_pixels(&data, 8), Ok((8, 3, vec![0xF0, 0x0F, 0x00])));
+}
+
+#[test]
+fn test_inspect_g4() {
+    use G4Event::*;
+    let data = crate::encoder::encode_g4([&[0u16, 4][..], &[1, 4][..]].iter().cloned(), 8);
+    assert_eq!(inspect_g4(&data, 8), [
+        LineStart(0),
+        ModeCode(Mode::Horizontal),
+        ColorTransition { position: 0, color: Color::Black },
+        ColorTransition { position: 4, color: Color::White },
+        ModeCode(Mode::Vertical(0)),
+        LineEnd(0),
+        LineStart(1),
+        ModeCode(Mode::Vertical(1)),
+        ColorTransition { position: 1, color: Color::Black },
+        ModeCode(Mode::Vertical(0)),
+        ColorTransition { position: 4, color: Color::White },
+        ModeCode(Mode::Vertical(0)),
+        LineEnd(1),
+        LineStart(2),
+        ModeCode(Mode::EOF),
+    ]);
+}
+
+#[test]
+fn test_inspect_g3() {
+    use G3Event::*;
+    let data = crate::encoder::encode_g3([&[3u16][..], &[][..]].iter().cloned(), 100);
+    let mut expected = vec![
+        Eol,
+        RunLength { color: Color::White, run: 3, make_up: false },
+        RunLength { color: Color::Black, run: 64, make_up: true },
+        RunLength { color: Color::Black, run: 33, make_up: false },
+        Eol,
+        RunLength { color: Color::White, run: 64, make_up: true },
+        RunLength { color: Color::White, run: 36, make_up: false },
+    ];
+    expected.extend_from_slice(&[Eol; 6]);
+    expected.push(Rtc);
+    assert_eq!(inspect_g3(&data), expected);
+
+    // no black code has eight leading zeros
+    use crate::{BitWriter, Bits, VecWriter};
+    let mut writer = VecWriter::new();
+    let white_3 = Bits { data: 0b1000, len: 4 };
+    let black_5 = Bits { data: 0b0011, len: 4 };
+    let invalid = Bits { data: 1, len: 9 };
+    for &bits in [EOL, white_3, invalid, EOL, white_3, black_5].iter().chain(&[EOL; 6]) {
+        writer.write(bits);
+    }
+    let mut expected = vec![
+        Eol,
+        RunLength { color: Color::White, run: 3, make_up: false },
+        InvalidCode { bit_position: 16 },
+        Eol,
+        RunLength { color: Color::White, run: 3, make_up: false },
+        RunLength { color: Color::Black, run: 5, make_up: false },
+    ];
+    expected.extend_from_slice(&[Eol; 6]);
+    expected.push(Rtc);
+    assert_eq!(inspect_g3(&writer.finish()), expected);
+}
+
+#[test]
+fn test_decode_g4_first_n_lines() {
+    let lines: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![y, 20]).collect();
+    let data = crate::encoder::encode_g4(lines.iter().map(|line| line.as_slice()), 32);
+    assert_eq!(decode_g4_first_n_lines(&data, 32, 3).unwrap(), lines[.. 3]);
+    assert_eq!(decode_g4_first_n_lines(&data, 32, 20).unwrap(), lines);
+    // the rest of the data is not looked at
+    assert_eq!(decode_g4_first_n_lines(&data[.. 4], 32, 1).unwrap(), lines[.. 1]);
+
+    // nor read
+    let lines: Vec<Vec<u16>> = (0 .. 100).map(|y| vec![y % 20, 20]).collect();
+    let data = crate::encoder::encode_g4(lines.iter().map(|line| line.as_slice()), 32);
+    let options = G4DecodeOptions { max_lines: Some(1), .. G4DecodeOptions::default() };
+    let mut read = 0;
+    decode_g4_with_options(data.iter().cloned().inspect(|_| read += 1), 32, &options, |_| ()).unwrap();
+    assert!(read < 8, "{} of {} bytes read", read, data.len());
+}
+
+#[test]
+fn test_decode_g4_skip() {
+    let lines: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![y, 20]).collect();
+    let data = crate::encoder::encode_g4(lines.iter().map(|line| line.as_slice()), 32);
+    let mut decoded = vec![];
+    decode_g4_skip(&data, 32, 4, Some(3), |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines[4 .. 7]);
+
+    let mut decoded = vec![];
+    decode_g4_skip(&data, 32, 8, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines[8 ..]);
+}
+
+#[test]
+fn test_g4_index() {
+    // every fifth line is blank
+    let lines: Vec<Vec<u16>> = (0 .. 30u16).map(|y| if y % 5 == 4 { vec![] } else { vec![y, 40 + y % 3] }).collect();
+    let data = crate::encoder::encode_g4(lines.iter().map(|line| line.as_slice()), 64);
+    let index = build_g4_index(&data, 64, 8);
+    let starts: Vec<u32> = index.iter().map(|&(line, _)| line).collect();
+    assert_eq!(starts, [0, 10, 20]);
+
+    for start in [0, 7, 10, 23, 29] {
+        let mut decoded = vec![];
+        decode_g4_from_index(&data, &index, 64, start, |line| decoded.push(line.to_vec())).unwrap();
+        assert_eq!(decoded, lines[start as usize ..], "start at {}", start);
+    }
+}
+
+#[test]
+fn test_g4_truncated_within_line() {
+    let lines: Vec<Vec<u16>> = vec![vec![10, 20], vec![30, 40, 50, 60]];
+    let data = crate::encoder::encode_g4(lines.iter().map(|l| l.as_slice()), 100);
+    // the first line takes two bytes (horizontal and Vertical(0)), the third one ends within the second line
+    let truncated = &data[.. 3];
+
+    let mut options = G4DecodeOptions { strict: true, .. G4DecodeOptions::default() };
+    let mut decoded = vec![];
+    let result = decode_g4_with_options(truncated.iter().cloned(), 100, &options, |line| decoded.push(line.to_vec()));
+    assert_eq!(result, Err(FaxError::UnexpectedEof));
+    assert_eq!(decoded, [vec![10, 20]]);
+
+    // of the second line, only the leading pass code is complete
+    options.strict = false;
+    let mut decoded = vec![];
+    decode_g4_with_options(truncated.iter().cloned(), 100, &options, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, [vec![10, 20], vec![]]);
+
+    // the same with a line limit
+    options.max_lines = Some(2);
+    let mut decoded = vec![];
+    decode_g4_with_options(truncated.iter().cloned(), 100, &options, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, [vec![10, 20], vec![]]);
+}
+
+#[test]
+fn test_g4_code_cut_off() {
+    // the data ends within a code that is longer than the bits the mode table is indexed with
+    let data = [0x6e, 0xb7, 0x58, 0x00];
+    assert_eq!(decode_g4(data.iter().cloned(), 1728, None, |_| ()), Some(()));
+    let options = G4DecodeOptions { strict: true, .. G4DecodeOptions::default() };
+    assert_eq!(decode_g4_with_options(data.iter().cloned(), 1728, &options, |_| ()), Err(FaxError::UnexpectedEof));
+}
+
+#[test]
+fn test_decode_g4_fixed() {
+    let data = include_bytes!("../stream/6");
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), 264, None, |line| lines.push(line.to_vec())).unwrap();
+
+    let mut buffer = [0; 2 * 264];
+    let mut fixed_lines = vec![];
+    decode_g4_fixed(&mut buffer, data.iter().cloned(), 264, None, |line| fixed_lines.push(line.to_vec())).unwrap();
+    assert_eq!(fixed_lines, lines);
+
+    let most = lines.iter().map(|line| line.len()).max().unwrap();
+    let mut buffer = vec![0; 2 * most];
+    assert_eq!(decode_g4_fixed(&mut buffer, data.iter().cloned(), 264, None, |_| ()), Ok(()));
+    buffer.truncate(2 * most - 2);
+    assert_eq!(decode_g4_fixed(&mut buffer, data.iter().cloned(), 264, None, |_| ()), Err(FaxError::BufferTooSmall));
+}
+
+#[test]
+fn test_g4_horizontal_overflow() {
+    use crate::{BitWriter, VecWriter, Bits};
+    let mut writer = VecWriter::new();
+    writer.write(Bits { data: 0b001, len: 3 });
+    // white 25 * 2560 + 0, black 2 * 2560 + 0
+    for _ in 0 .. 25 {
+        writer.write(Bits { data: 0b000000011111, len: 12 });
+    }
+    writer.write(Bits { data: 0b00110101, len: 8 });
+    for _ in 0 .. 2 {
+        writer.write(Bits { data: 0b000000011111, len: 12 });
+    }
+    writer.write(Bits { data: 0b0000110111, len: 10 });
+    let data = writer.finish();
+    let options = G4DecodeOptions::default();
+    assert_eq!(decode_g4_with_options(data.iter().cloned(), 100, &options, |_| ()), Err(FaxError::InvalidCode));
+}
+
+#[test]
+fn test_g4_truncated_in_horizontal() {
+    // nine Vertical(0) lines, then Horizontal, white 10 and black 10: 9 + 3 + 5 + 7 bits
+    let data = [0xff, 0x93, 0x84];
+    let empty = vec![vec![]; 9];
+
+    // the data ends right after the black run, whose code is shorter than the bits the table is indexed with
+    let mut options = G4DecodeOptions { strict: true, .. G4DecodeOptions::default() };
+    let mut decoded: Vec<Vec<u16>> = vec![];
+    let result = decode_g4_with_options(data.iter().cloned(), 100, &options, |line| decoded.push(line.to_vec()));
+    assert_eq!(result, Err(FaxError::UnexpectedEof));
+    assert_eq!(decoded, empty);
+
+    options.strict = false;
+    let mut decoded = vec![];
+    decode_g4_with_options(data.iter().cloned(), 100, &options, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded[.. 9], empty[..]);
+    assert_eq!(decoded[9 ..], [vec![10, 20]]);
+
+    // the data ends within the white run
+    options.strict = true;
+    let mut decoded: Vec<Vec<u16>> = vec![];
+    let result = decode_g4_with_options(data[.. 2].iter().cloned(), 100, &options, |line| decoded.push(line.to_vec()));
+    assert_eq!(result, Err(FaxError::UnexpectedEof));
+    assert_eq!(decoded, empty);
+
+    options.strict = false;
+    let mut decoded = vec![];
+    decode_g4_with_options(data[.. 2].iter().cloned(), 100, &options, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded[.. 9], empty[..]);
+    assert_eq!(decoded[9 ..], [vec![]]);
+}
 
-    Some(())
+#[test]
+fn test_decode_g4_with_progress() {
+    let lines: Vec<Vec<u16>> = (0 .. 250).map(|y| vec![y % 50, 60]).collect();
+    let data = crate::encoder::encode_g4(lines.iter().map(|l| l.as_slice()), 64);
+    let mut decoded = vec![];
+    let mut progress = vec![];
+    decode_g4_with_progress(data.iter().cloned(), 64, None, |line| decoded.push(line.to_vec()), |n| progress.push(n)).unwrap();
+    assert_eq!(decoded, lines);
+    assert_eq!(progress, [100, 200]);
 }
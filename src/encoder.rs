@@ -1,4 +1,4 @@
-use crate::{Color, BitWriter, Transitions, maps::{Mode, mode, black, white, EDFB_HALF}};
+use crate::{Color, BitWriter, Bits, FaxError, transitions, maps::{Mode, mode, black, white, EDFB_HALF, EOL}};
 
 fn absdiff(a: u16, b: u16) -> u16 {
     if a > b {
@@ -12,135 +12,888 @@ pub struct Encoder<W> {
     writer: W,
     reference: Vec<u16>,
     current: Vec<u16>,
+    stats: G4EncodeStats,
+    memory: G4MemoryStats,
+    total_bits: u64,
 }
-fn encode_color(writer: &mut impl BitWriter, color: Color, mut n: u16) {
+
+/// Sizes seen by an `Encoder`, for capacity planning.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct G4MemoryStats {
+    /// The most color changes in a single line, which is what the line buffers have to hold
+    pub peak_transition_count: usize,
+    /// Number of bytes of coded data (not counting the end-of-block marker)
+    pub bytes_written: usize,
+    /// Number of lines encoded
+    pub lines_encoded: u32,
+}
+
+/// Statistics about the modes chosen by the encoder.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct G4EncodeStats {
+    /// Number of pass mode codes
+    pub pass_count: u64,
+    /// Number of vertical mode codes, indexed by `delta + 3`
+    pub vertical: [u64; 7],
+    /// Number of horizontal mode codes
+    pub horizontal_count: u64,
+    /// Number of bits written (not counting the end-of-block marker)
+    pub bits_written: u64,
+}
+impl G4EncodeStats {
+    fn record(&mut self, mode: Mode) {
+        match mode {
+            Mode::Pass => self.pass_count += 1,
+            Mode::Vertical(delta) => self.vertical[(delta + 3) as usize] += 1,
+            Mode::Horizontal => self.horizontal_count += 1,
+            Mode::Extension | Mode::EOF => {}
+        }
+    }
+    fn mode_count(&self) -> u64 {
+        self.pass_count + self.vertical.iter().sum::<u64>() + self.horizontal_count
+    }
+
+    /// Fraction of all mode codes which are vertical mode codes.
+    pub fn vertical_fraction(&self) -> f64 {
+        match self.mode_count() {
+            0 => 0.0,
+            n => self.vertical.iter().sum::<u64>() as f64 / n as f64
+        }
+    }
+
+    /// Fraction of all mode codes which are horizontal mode codes.
+    pub fn horizontal_fraction(&self) -> f64 {
+        match self.mode_count() {
+            0 => 0.0,
+            n => self.horizontal_count as f64 / n as f64
+        }
+    }
+}
+
+/// Counts the bits passing through to `writer`.
+struct Counted<'a, W> {
+    writer: &'a mut W,
+    bits: u64,
+}
+impl<W: BitWriter> BitWriter for Counted<'_, W> {
+    fn write(&mut self, bits: Bits) {
+        self.bits += bits.len as u64;
+        self.writer.write(bits);
+    }
+}
+/// Split a run of `n` pixels into make-up and terminating codes, as they are written.
+pub(crate) fn run_codes(mut n: u16) -> impl Iterator<Item=u16> {
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let d = match n {
+            2560 ..= u16::MAX => 2560,
+            64 ..= 2559 => n & !63,
+            _ => {
+                done = true;
+                n
+            }
+        };
+        n -= d;
+        Some(d)
+    })
+}
+
+/// The code for a make-up (`n >= 64`) or terminating (`n < 64`) code of the given color.
+pub(crate) fn color_code(color: Color, n: u16) -> Bits {
     let table = match color {
         Color::White => &white::ENTRIES,
         Color::Black => &black::ENTRIES,
     };
-    let mut write = |n: u16| {
-        let idx = if n >= 64 { 63 + n / 64 } else { n } as usize;
-        let (v, bits) = table[idx];
-        assert_eq!(v, n);
-        //println!("{}", n);
-        writer.write(bits);
-    };
-    
-    while n >= 2560 {
-        write(2560);
-        n -= 2560;
-    }
-    if n >= 64 {
-        let d = n & !63;
-        write(d);
-        n -= d;
+    let idx = if n >= 64 { 63 + n / 64 } else { n } as usize;
+    let (v, bits) = table[idx];
+    assert_eq!(v, n);
+    bits
+}
+
+fn encode_color(writer: &mut impl BitWriter, color: Color, n: u16) {
+    for code in run_codes(n) {
+        //println!("{}", code);
+        writer.write(color_code(color, code));
     }
+}
+/// Code `current` relative to `reference` using the two-dimensional coding of T.4 and T.6.
+/// 
+/// For each changing element, pass mode is used if `b2` is left of `a1`,
+/// vertical mode if `a1` is within 3 pixels of `b1` and horizontal mode otherwise.
+fn encode_2d(writer: &mut impl BitWriter, stats: &mut G4EncodeStats, reference: &[u16], current: &[u16], width: u16) {
+    // identical lines (like the blank lines of a page) are coded as V0 codes only
+    if current == reference {
+        let v0 = mode::encode(Mode::Vertical(0)).unwrap();
+        let n = current.iter().take_while(|&&p| p < width).count() + 1;
+        stats.vertical[3] += n as u64;
+        for _ in 0 .. n {
+            writer.write(v0);
+        }
+        return;
+    }
+    let mut write_mode = |writer: &mut _, mode| {
+        stats.record(mode);
+        BitWriter::write(writer, mode::encode(mode).unwrap());
+    };
+    // `None` is the imaginary white pixel in front of the line
+    let mut a0: Option<u16> = None;
+    let mut color = Color::White;
+    // first changing element right of a0 (in current and reference)
+    let mut ia = 0;
+    let mut ib = 0;
+    loop {
+        if let Some(a0) = a0 {
+            while ia < current.len() && current[ia] <= a0 {
+                ia += 1;
+            }
+            while ib < reference.len() && reference[ib] <= a0 {
+                ib += 1;
+            }
+        }
+        let a1 = current.get(ia).cloned().unwrap_or(width);
+
+        // b1 has the opposite color of a0. Changes to black have an even index.
+        let jb = if (ib % 2 == 0) == (color == Color::White) { ib } else { ib + 1 };
+        let b1 = reference.get(jb).cloned().unwrap_or(width);
+        let b2 = reference.get(jb + 1).cloned().unwrap_or(width);
+        //println!("a0={:?}, a1={}, b1={}, b2={}", a0, a1, b1, b2);
 
-    write(n);
+        if b2 < a1 {
+            write_mode(writer, Mode::Pass);
+            a0 = Some(b2);
+        } else if absdiff(a1, b1) <= 3 {
+            let delta = a1 as i16 - b1 as i16;
+            write_mode(writer, Mode::Vertical(delta as i8));
+            a0 = Some(a1);
+            color = !color;
+        } else {
+            let a2 = current.get(ia + 1).cloned().unwrap_or(width);
+            let a0a1 = a1 - a0.unwrap_or(0);
+            let a1a2 = a2 - a1;
+            write_mode(writer, Mode::Horizontal);
+            encode_color(writer, color, a0a1);
+            encode_color(writer, !color, a1a2);
+            a0 = Some(a2);
+        }
+
+        if a0 >= Some(width) {
+            break;
+        }
+    }
 }
+
 impl<W: BitWriter> Encoder<W> {
     pub fn new(writer: W) -> Self {
         Encoder {
             writer,
             reference: vec![],
             current: vec![],
+            stats: G4EncodeStats::default(),
+            memory: G4MemoryStats::default(),
+            total_bits: 0,
         }
     }
+
+    /// Create an encoder that codes the first line relative to `reference` instead of an all-white line.
+    /// 
+    /// This is the counterpart of `decode_g4_with_reference`.
+    pub fn with_reference(writer: W, reference: &[u16]) -> Self {
+        Encoder {
+            reference: reference.to_vec(),
+            .. Encoder::new(writer)
+        }
+    }
+
     pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
         let mut color = Color::White;
-        let mut transitions = Transitions::new(&self.reference);
-        let mut a0 = 0;
-        let mut start_of_row = true;
-        let mut pels = pels.enumerate()
-        .scan(Color::White, |state, (i, c)| {
-            Some(if c != *state {
-                *state = c;
-                Some(i as u16)
-            } else {
-                None
-            })
-        }).filter_map(|x| x);
-        let writer = &mut self.writer;
         self.current.clear();
-
-        while let Some(a1) = pels.next() {
-            //println!("a1={}", a1);
-            self.current.push(a1);
-            loop {
-                transitions.seek_back(a0);
-                let b1 = transitions.next_color(a0, !color, start_of_row);
-                let b2 = transitions.peek();
-
-                start_of_row = false;
-                //println!("b1={:?}, b2={:?}", b1, b2);
-                match (b1, b2) {
-                    (Some(_b1), Some(b2)) if b2 < a1 => {
-                        //println!("Pass");
-                        let bits = mode::encode(Mode::Pass).unwrap();
-                        writer.write(bits);
-                        transitions.skip(1);
-                        a0 = b2;
-                        continue;
-                    }
-                    (Some(b1), _) if absdiff(a1, b1) <= 3 => {
-                        let delta = a1 as i16 - b1 as i16;
-                        //println!("Vertical({})", delta);
-                        let bits = mode::encode(Mode::Vertical(delta as i8)).unwrap();
-                        writer.write(bits);
-                        a0 = a1;
-                        color = !color;
-                    }
-                    _ => {
-                        let a2 = match pels.next() {
-                            Some(a2) => {
-                                self.current.push(a2);
-                                a2
-                            },
-                            None => width
-                        };
-                        let bits = mode::encode(Mode::Horizontal).unwrap();
-                        writer.write(bits);
-                        let a0a1 = a1 - a0;
-                        let a1a2 = a2 - a1;
-                        //println!("Horizontal({}, {})", a0a1, a1a2);
-                        encode_color(writer, color, a0a1);
-                        encode_color(writer, !color, a1a2);
-                        a0 = a2;
-                    }
-                }
-                break;
+        for (i, c) in pels.enumerate() {
+            if c != color {
+                self.current.push(i as u16);
+                color = c;
             }
         }
-        transitions.seek_back(a0);
-        loop {
-            let b1 = transitions.next_color(a0, !color, start_of_row);
-            let b2 = transitions.peek();
-            //println!("b1={:?}, b2={:?}", b1, b2);
-            if let Some(b1) = b1 {
-                //println!("Pass");
-                let bits = mode::encode(Mode::Pass).unwrap();
-                writer.write(bits);
-                transitions.skip(1);
-                if let Some(b2) = b2 {
-                    a0 = b2;
-                } else {
-                    a0 = b1;
-                    break;
-                }
-            } else {
-                break;
+        self.encode_current(width);
+    }
+
+    /// Encode a line given as list of color changes, starting with white.
+    pub fn encode_transitions(&mut self, transitions: &[u16], width: u16) {
+        self.current.clear();
+        self.current.extend_from_slice(transitions);
+        self.encode_current(width);
+    }
+
+    fn encode_current(&mut self, width: u16) {
+        let mut writer = Counted { writer: &mut self.writer, bits: 0 };
+        encode_2d(&mut writer, &mut self.stats, &self.reference, &self.current, width);
+        self.stats.bits_written += writer.bits;
+        self.total_bits += writer.bits;
+        self.memory.peak_transition_count = self.memory.peak_transition_count.max(self.current.len());
+        self.memory.lines_encoded += 1;
+        std::mem::swap(&mut self.reference, &mut self.current);
+    }
+
+    /// The sizes seen since the start of encoding.
+    pub fn memory_stats(&self) -> G4MemoryStats {
+        G4MemoryStats {
+            bytes_written: self.total_bits.div_ceil(8) as usize,
+            .. self.memory
+        }
+    }
+
+    /// Return the statistics accumulated since the last call (or since the start) and reset them.
+    pub fn take_stats(&mut self) -> G4EncodeStats {
+        std::mem::take(&mut self.stats)
+    }
+    pub fn finish(mut self) -> W {
+        self.write_eofb();
+        self.writer
+    }
+    fn write_eofb(&mut self) {
+        self.writer.write(EDFB_HALF);
+        self.writer.write(EDFB_HALF);
+    }
+}
+
+/// Group 4 encoder writing to a `tokio::io::AsyncWrite`.
+/// 
+/// The lines are coded by `Encoder`, the complete bytes are written after each line.
+#[cfg(feature = "tokio")]
+pub struct AsyncG4Encoder<W> {
+    encoder: Encoder<crate::VecWriter>,
+    writer: W,
+    width: u16,
+}
+#[cfg(feature = "tokio")]
+impl<W: tokio::io::AsyncWrite + Unpin> AsyncG4Encoder<W> {
+    pub fn new(writer: W, width: u16) -> Self {
+        AsyncG4Encoder {
+            encoder: Encoder::new(crate::VecWriter::new()),
+            writer,
+            width,
+        }
+    }
+
+    /// Encode a line given as list of color changes, starting with white.
+    pub async fn write_line(&mut self, line: &[u16]) -> Result<(), crate::FaxError> {
+        use tokio::io::AsyncWriteExt;
+        self.encoder.encode_transitions(line, self.width);
+        let bytes = self.encoder.writer.take_bytes();
+        self.writer.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Write the end-of-block marker and flush the writer.
+    /// 
+    /// No lines can be written afterwards.
+    pub async fn finish(&mut self) -> Result<(), crate::FaxError> {
+        use tokio::io::AsyncWriteExt;
+        self.encoder.write_eofb();
+        self.encoder.writer.pad();
+        let bytes = self.encoder.writer.take_bytes();
+        self.writer.write_all(&bytes).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Code `current` as a sequence of alternating white and black runs (Modified Huffman).
+pub(crate) fn encode_1d(writer: &mut impl BitWriter, current: &[u16], width: u16) {
+    let mut color = Color::White;
+    let mut a0 = 0;
+    for &a1 in current.iter().chain(std::iter::once(&width)) {
+        encode_color(writer, color, a1 - a0);
+        a0 = a1;
+        color = !color;
+    }
+}
+
+/// Encoder for Group 3 (T.4) images.
+/// 
+/// Each line starts with an EOL and `finish` appends the RTC.
+/// By default, all lines are coded one-dimensionally. See `with_k_factor` for two-dimensional coding.
+pub struct G3Encoder<W> {
+    writer: W,
+    reference: Vec<u16>,
+    current: Vec<u16>,
+    k: u8,
+    /// lines since the last one-dimensionally coded line
+    line: u8,
+    /// no line has been coded yet
+    empty: bool,
+}
+impl<W: BitWriter> G3Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        G3Encoder {
+            writer,
+            reference: vec![],
+            current: vec![],
+            k: 1,
+            line: 0,
+            empty: true,
+        }
+    }
+
+    /// Code every `k`-th line one-dimensionally and the lines in between two-dimensionally. (Modified READ)
+    /// 
+    /// T.4 uses `k = 2` for standard and `k = 4` for fine resolution.
+    /// `k = 1` codes all lines one-dimensionally, without tag bits.
+    /// 
+    /// Panics if `k` is not 1, 2 or 4.
+    pub fn with_k_factor(self, k: u8) -> Self {
+        assert!(matches!(k, 1 | 2 | 4), "invalid K factor {}", k);
+        G3Encoder { k, .. self }
+    }
+
+    pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
+        let mut color = Color::White;
+        self.current.clear();
+        for (i, c) in pels.enumerate() {
+            if c != color {
+                self.current.push(i as u16);
+                color = c;
             }
         }
-        if a0 < width {
-            //println!("Vertical(0)");
-            let bits = mode::encode(Mode::Vertical(0)).unwrap();
-            writer.write(bits);
+        self.encode_current(width);
+    }
+
+    /// Encode a line given as list of color changes, starting with white.
+    pub fn encode_transitions(&mut self, transitions: &[u16], width: u16) {
+        self.current.clear();
+        self.current.extend_from_slice(transitions);
+        self.encode_current(width);
+    }
+
+    fn encode_current(&mut self, width: u16) {
+        self.writer.write(EOL);
+        if self.k == 1 {
+            encode_1d(&mut self.writer, &self.current, width);
+        } else if self.line == 0 {
+            self.writer.write(Bits { data: 1, len: 1 });
+            encode_1d(&mut self.writer, &self.current, width);
+        } else {
+            self.writer.write(Bits { data: 0, len: 1 });
+            encode_2d(&mut self.writer, &mut G4EncodeStats::default(), &self.reference, &self.current, width);
         }
+        self.line = (self.line + 1) % self.k;
+        self.empty = false;
         std::mem::swap(&mut self.reference, &mut self.current);
     }
+
+    /// Write the RTC (six EOLs) and return the writer.
+    /// 
+    /// Without any lines, the leading EOL is written as well.
     pub fn finish(mut self) -> W {
-        self.writer.write(EDFB_HALF);
-        self.writer.write(EDFB_HALF);
+        let eols = if self.empty { 7 } else { 6 };
+        for _ in 0 .. eols {
+            self.writer.write(EOL);
+            if self.k != 1 {
+                self.writer.write(Bits { data: 1, len: 1 });
+            }
+        }
         self.writer
     }
-}
\ No newline at end of file
+}
+
+/// Encode `lines` (given as lists of color changes) as one-dimensional Group 3.
+pub fn encode_g3<'a>(lines: impl Iterator<Item=&'a [u16]>, width: u16) -> Vec<u8> {
+    let mut encoder = G3Encoder::new(crate::VecWriter::new());
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder.finish().finish()
+}
+
+/// Threshold for `encode_g4_from_gray_image` that splits the range of gray values in half.
+#[cfg(feature = "image")]
+pub const DEFAULT_THRESHOLD: u8 = 128;
+
+/// Encode a grayscale image as Group 4.
+/// 
+/// Pixels with a value of at least `threshold` are white, darker ones are black.
+/// `DEFAULT_THRESHOLD` is a good choice if there is no better one.
+/// Returns `FaxError::WidthMismatch` if the image is wider than 65535 pixels.
+#[cfg(feature = "image")]
+pub fn encode_g4_from_gray_image(img: &image::GrayImage, threshold: u8) -> Result<Vec<u8>, FaxError> {
+    if img.width() > u16::MAX as u32 {
+        return Err(FaxError::WidthMismatch);
+    }
+    let width = img.width() as u16;
+    let mut encoder = Encoder::new(crate::VecWriter::new());
+    for row in img.rows() {
+        let pels = row.map(|p| if p.0[0] >= threshold { Color::White } else { Color::Black });
+        encoder.encode_line(pels, width);
+    }
+    Ok(encoder.finish().finish())
+}
+
+/// Encode `lines` (given as lists of color changes) as Group 4, including the end-of-block marker.
+pub fn encode_g4<'a>(lines: impl Iterator<Item=&'a [u16]>, width: u16) -> Vec<u8> {
+    let mut encoder = Encoder::new(crate::VecWriter::new());
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder.finish().finish()
+}
+
+/// Encode a packed bitmap of `height` rows as Group 4.
+/// 
+/// Each row takes `(width + 7) / 8` bytes, one bit per pixel with `1` for black.
+/// Returns `FaxError::UnexpectedEof` if `pixels` is too short and `FaxError::WidthMismatch` if `width` is zero.
+pub fn pixels_to_g4(width: u16, height: u32, pixels: &[u8]) -> Result<Vec<u8>, FaxError> {
+    if width == 0 {
+        return Err(FaxError::WidthMismatch);
+    }
+    let row_bytes = (width as usize).div_ceil(8);
+    let rows = pixels.chunks(row_bytes).take(height as usize);
+    if pixels.len() < row_bytes * height as usize {
+        return Err(FaxError::UnexpectedEof);
+    }
+    let lines: Vec<Vec<u16>> = rows.map(|row| transitions::packed_pixels_to_transitions(row, width)).collect();
+    Ok(encode_g4(lines.iter().map(|line| line.as_slice()), width))
+}
+
+/// Encode rows of pixels as one-dimensional Group 3.
+/// 
+/// A pixel is black if it equals `black_is_true`. Pixels beyond `width` are ignored, missing ones are white.
+pub fn encode_g3_from_bool_rows(rows: impl Iterator<Item=impl AsRef<[bool]>>, width: u16, black_is_true: bool) -> Vec<u8> {
+    let mut encoder = G3Encoder::new(crate::VecWriter::new());
+    for row in rows {
+        let row = row.as_ref();
+        let line = transitions::from_bool_slice(&row[.. row.len().min(width as usize)], black_is_true);
+        encoder.encode_transitions(&line, width);
+    }
+    encoder.finish().finish()
+}
+
+/// Encode rows of pixels as Group 4.
+/// 
+/// A pixel is black if it equals `black_is_true`. Pixels beyond `width` are ignored, missing ones are white.
+pub fn encode_g4_from_bool_rows(rows: impl Iterator<Item=impl AsRef<[bool]>>, width: u16, black_is_true: bool) -> Vec<u8> {
+    let mut encoder = Encoder::new(crate::VecWriter::new());
+    for row in rows {
+        let row = row.as_ref();
+        let line = transitions::from_bool_slice(&row[.. row.len().min(width as usize)], black_is_true);
+        encoder.encode_transitions(&line, width);
+    }
+    encoder.finish().finish()
+}
+
+/// Encode `lines` as Group 4 using horizontal mode for every pair of runs.
+/// 
+/// This is the simplest correct coding, without pass and vertical modes.
+/// It is meant as a baseline when comparing encoders, see `compare_g4_sizes`.
+pub fn encode_g4_horizontal(lines: &[Vec<u16>], width: u16) -> Vec<u8> {
+    let mut writer = crate::VecWriter::new();
+    for line in lines {
+        let mut changes = line.iter().cloned();
+        let mut a0 = 0;
+        while a0 < width {
+            let a1 = changes.next().unwrap_or(width);
+            let a2 = changes.next().unwrap_or(width);
+            writer.write(mode::encode(Mode::Horizontal).unwrap());
+            encode_color(&mut writer, Color::White, a1 - a0);
+            encode_color(&mut writer, Color::Black, a2 - a1);
+            a0 = a2;
+        }
+    }
+    writer.write(EDFB_HALF);
+    writer.write(EDFB_HALF);
+    writer.finish()
+}
+
+/// Ratio of the size produced by `Encoder` to the size produced by `reference_encoder`.
+/// 
+/// `uncompressed_pixels` holds the rows of the image, packed as by `decoder::pels_packed`
+/// (one bit per pixel, `1` is black, each row padded to a byte).
+/// The reference encoder is given the rows as lists of color changes.
+pub fn compare_g4_sizes(uncompressed_pixels: &[u8], width: u16, reference_encoder: impl Fn(&[Vec<u16>]) -> Vec<u8>) -> f64 {
+    let lines: Vec<Vec<u16>> = uncompressed_pixels.chunks((width as usize).div_ceil(8))
+        .map(|row| transitions::packed_pixels_to_transitions(row, width))
+        .collect();
+    let ours = encode_g4(lines.iter().map(|line| line.as_slice()), width);
+    ours.len() as f64 / reference_encoder(&lines).len() as f64
+}
+
+/// A `BitWriter` that only counts the number of bits written to it.
+struct BitCounter {
+    bits: usize
+}
+impl BitWriter for BitCounter {
+    fn write(&mut self, bits: Bits) {
+        self.bits += bits.len as usize;
+    }
+}
+
+/// Estimate the number of bits a line takes when encoded as Group 4.
+///
+/// `current` is coded relative to `reference`, both given as lists of color changing positions.
+/// The same mode selection as in `Encoder` is performed, but instead of writing
+/// the codewords, only their lengths are summed up.
+pub fn estimate_g4_bits(reference: &[u16], current: &[u16], width: u16) -> usize {
+    let mut counter = BitCounter { bits: 0 };
+    encode_2d(&mut counter, &mut G4EncodeStats::default(), reference, current, width);
+    counter.bits
+}
+
+/// Number of bits `encode_g3` produces for `lines`, before padding to a byte.
+/// 
+/// This includes the EOLs and the RTC. No output is allocated.
+pub fn estimate_g3_compressed_size(lines: &[&[u16]], width: u16) -> usize {
+    let mut encoder = G3Encoder::new(BitCounter { bits: 0 });
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder.finish().bits
+}
+
+/// Number of bits `encode_g4` produces for `lines`, before padding to a byte.
+/// 
+/// This includes the end-of-block marker. No output is allocated.
+pub fn estimate_g4_compressed_size(lines: &[&[u16]], width: u16) -> usize {
+    let mut encoder = Encoder::new(BitCounter { bits: 0 });
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder.finish().bits
+}
+
+/// Encode a single line as Group 4, relative to `reference`.
+/// 
+/// Returns the coded bytes and the number of valid bits in them.
+/// The bits after that in the last byte are zero.
+pub fn encode_g4_line_bits(reference: &[u16], current: &[u16], width: u16) -> (Vec<u8>, usize) {
+    let mut writer = crate::VecWriter::new();
+    let mut counted = Counted { writer: &mut writer, bits: 0 };
+    encode_2d(&mut counted, &mut G4EncodeStats::default(), reference, current, width);
+    let bits = counted.bits as usize;
+    (writer.finish(), bits)
+}
+
+#[test]
+fn test_estimate_white_line() {
+    // a white line below a white line is a single Vertical(0) code
+    assert_eq!(estimate_g4_bits(&[], &[], 1728), 1);
+}
+
+#[test]
+fn test_estimate_compressed_size() {
+    let lines: Vec<Vec<u16>> = (0 .. 20).map(|y| vec![y, 3 * y + 20, 90]).collect();
+    let lines: Vec<&[u16]> = lines.iter().map(|l| l.as_slice()).collect();
+    let g3 = estimate_g3_compressed_size(&lines, 100);
+    assert_eq!((g3 + 7) / 8, encode_g3(lines.iter().cloned(), 100).len());
+    let g4 = estimate_g4_compressed_size(&lines, 100);
+    assert_eq!((g4 + 7) / 8, encode_g4(lines.iter().cloned(), 100).len());
+    assert!(g4 < g3);
+}
+
+#[test]
+fn test_take_stats() {
+    let mut encoder = Encoder::new(BitCounter { bits: 0 });
+    encoder.encode_line(std::iter::repeat(Color::White).take(100), 100);
+    let stats = encoder.take_stats();
+    assert_eq!(stats.vertical[3], 1);
+    assert_eq!(stats.bits_written, 1);
+    assert_eq!(stats.vertical_fraction(), 1.0);
+    assert_eq!(encoder.take_stats(), G4EncodeStats::default());
+}
+
+#[test]
+fn test_g3_k_factor() {
+    use crate::decoder::{decode_g3_with_options, G3DecodeOptions};
+    let lines: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![y, 2 * y + 10, 50]).collect();
+    for &k in [1, 2, 4].iter() {
+        let mut encoder = G3Encoder::new(crate::VecWriter::new()).with_k_factor(k);
+        for line in &lines {
+            encoder.encode_transitions(line, 100);
+        }
+        let data = encoder.finish().finish();
+        let options = G3DecodeOptions {
+            width: Some(100),
+            two_dimensional: k != 1,
+            strict: true,
+            omit_line_end: true,
+            .. G3DecodeOptions::default()
+        };
+        let mut decoded = vec![];
+        decode_g3_with_options(data.iter().cloned(), &options, |line| decoded.push(line.to_vec())).unwrap();
+        assert_eq!(decoded, lines, "k = {}", k);
+    }
+}
+
+#[test]
+fn test_encode_g4_line_bits() {
+    assert_eq!(encode_g4_line_bits(&[], &[], 1728), (vec![0b1000_0000], 1));
+    let (data, bits) = encode_g4_line_bits(&[], &[10, 20], 100);
+    assert_eq!(bits, estimate_g4_bits(&[], &[10, 20], 100));
+    assert_eq!(data.len(), (bits + 7) / 8);
+}
+
+/// Smallest number of bits any valid choice of vertical and horizontal modes takes for `current`.
+#[cfg(test)]
+fn optimal_g4_bits(reference: &[u16], current: &[u16], width: u16) -> usize {
+    use std::collections::HashMap;
+    fn run_bits(color: Color, n: u16) -> usize {
+        run_codes(n).map(|n| color_code(color, n).len as usize).sum()
+    }
+    fn go(memo: &mut HashMap<(Option<u16>, bool), usize>, reference: &[u16], current: &[u16], width: u16, a0: Option<u16>, color: Color) -> usize {
+        if a0 >= Some(width) {
+            return 0;
+        }
+        if let Some(&n) = memo.get(&(a0, color == Color::White)) {
+            return n;
+        }
+        let after = |line: &[u16]| match a0 {
+            Some(a0) => line.partition_point(|&p| p <= a0),
+            None => 0,
+        };
+        let (ia, ib) = (after(current), after(reference));
+        let a1 = current.get(ia).cloned().unwrap_or(width);
+        let jb = if (ib % 2 == 0) == (color == Color::White) { ib } else { ib + 1 };
+        let b1 = reference.get(jb).cloned().unwrap_or(width);
+        let b2 = reference.get(jb + 1).cloned().unwrap_or(width);
+
+        let bits = if b2 < a1 {
+            // pass mode is the only choice
+            4 + go(memo, reference, current, width, Some(b2), color)
+        } else {
+            let a2 = current.get(ia + 1).cloned().unwrap_or(width);
+            let horizontal = 3 + run_bits(color, a1 - a0.unwrap_or(0)) + run_bits(!color, a2 - a1)
+                + go(memo, reference, current, width, Some(a2), color);
+            if absdiff(a1, b1) <= 3 {
+                let code = mode::encode(Mode::Vertical((a1 as i16 - b1 as i16) as i8)).unwrap();
+                let vertical = code.len as usize + go(memo, reference, current, width, Some(a1), !color);
+                vertical.min(horizontal)
+            } else {
+                horizontal
+            }
+        };
+        memo.insert((a0, color == Color::White), bits);
+        bits
+    }
+    go(&mut HashMap::new(), reference, current, width, None, Color::White)
+}
+
+#[test]
+fn test_mode_choice_near_optimal() {
+    // 10 black pixels, evenly spaced
+    let dots = |shift: u16| -> Vec<u16> {
+        (0 .. 10).flat_map(|k| vec![86 + 172 * k + shift, 87 + 172 * k + shift]).collect()
+    };
+    let reference = dots(0);
+    for &(reference, shift) in [(&[][..], 0), (&reference[..], 0), (&reference[..], 1), (&reference[..], 3), (&reference[..], 5)].iter() {
+        let current = dots(shift);
+        let bits = estimate_g4_bits(reference, &current, 1728);
+        let optimal = optimal_g4_bits(reference, &current, 1728);
+        assert!(bits * 10 <= optimal * 11, "shift {}: {} bits, optimal {}", shift, bits, optimal);
+    }
+}
+
+#[test]
+fn test_encode_from_bool_rows() {
+    let rows = [vec![false; 8], vec![true, true, false, false, false, false, false, false]];
+    let lines = [vec![], vec![0, 2]];
+    assert_eq!(encode_g4_from_bool_rows(rows.iter(), 8, true), encode_g4(lines.iter().map(|l| l.as_slice()), 8));
+    assert_eq!(encode_g3_from_bool_rows(rows.iter(), 8, true), encode_g3(lines.iter().map(|l| l.as_slice()), 8));
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_encode_g4_from_gray_image() {
+    use crate::decoder::decode_g4;
+    // a dark block on a light background, with a gray pixel on each side of the threshold
+    let img = image::GrayImage::from_fn(20, 4, |x, y| match (x, y) {
+        (5 ..= 9, 1 ..= 2) => image::Luma([10]),
+        (15, 0) => image::Luma([DEFAULT_THRESHOLD - 1]),
+        (16, 0) => image::Luma([DEFAULT_THRESHOLD]),
+        _ => image::Luma([250]),
+    });
+    let data = encode_g4_from_gray_image(&img, DEFAULT_THRESHOLD).unwrap();
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), 20, Some(4), |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, [vec![15, 16], vec![5, 10], vec![5, 10], vec![]]);
+
+    let wide = image::GrayImage::new(65536, 1);
+    assert_eq!(encode_g4_from_gray_image(&wide, DEFAULT_THRESHOLD), Err(FaxError::WidthMismatch));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_encoder() {
+    let lines = [vec![], vec![10, 20], vec![12, 30]];
+    let mut encoder = AsyncG4Encoder::new(vec![], 100);
+    for line in &lines {
+        encoder.write_line(line).await.unwrap();
+    }
+    encoder.finish().await.unwrap();
+    assert_eq!(encoder.writer, encode_g4(lines.iter().map(|l| l.as_slice()), 100));
+}
+
+#[test]
+fn test_no_horizontal_for_small_deltas() {
+    use crate::{ByteReader, VecWriter};
+    let width = 300;
+    // every change moves by at most 3 pixels from the line above
+    let lines: Vec<Vec<u16>> = (0 .. 50u16)
+        .map(|y| vec![100 + y % 4, 150 - y % 3, 200 + 3 * (y % 2), 250])
+        .collect();
+    let mut encoder = Encoder::with_reference(VecWriter::new(), &lines[0]);
+    for line in &lines {
+        encoder.encode_transitions(line, width);
+    }
+    let data = encoder.finish().finish();
+
+    // without horizontal codes, the stream consists of mode codes only
+    let mut reader = ByteReader::from_slice(&data);
+    let mut codes = 0;
+    loop {
+        match mode::decode(&mut reader).unwrap() {
+            Mode::Pass | Mode::Vertical(_) => codes += 1,
+            Mode::EOF => break,
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+    // one per change, and one for the end of the line
+    assert_eq!(codes, 5 * lines.len());
+}
+
+#[test]
+fn test_compare_g4_sizes() {
+    use crate::decoder::{decode_g4, pels_packed};
+    let width = 200;
+    let lines: Vec<Vec<u16>> = (0 .. 100).map(|y| vec![10 + y / 2, 60 + y / 2, 150 - y / 4, 160 + y / 3]).collect();
+
+    // the baseline is valid Group 4
+    let data = encode_g4_horizontal(&lines, width);
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+
+    let pixels: Vec<u8> = lines.iter().flat_map(|line| pels_packed(line, width)).collect();
+    let ratio = compare_g4_sizes(&pixels, width, |lines| encode_g4_horizontal(lines, width));
+    assert!(ratio <= 1.1, "ratio {}", ratio);
+}
+
+#[test]
+fn test_single_color_lines() {
+    use crate::decoder::decode_g4;
+    let width = 100;
+    // an all-black line below a black one takes a V0 code at the start and one at the end,
+    // a white line below a white one a single V0 code
+    let lines = [vec![0], vec![0], vec![], vec![]];
+    let mut encoder = Encoder::new(crate::VecWriter::new());
+    for line in &lines {
+        encoder.encode_transitions(line, width);
+    }
+    let stats = encoder.take_stats();
+    assert_eq!(stats.vertical[3], 2 + 1);
+    assert_eq!(stats.horizontal_count, 2);
+    let data = encoder.finish().finish();
+
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+}
+
+#[test]
+fn test_pixels_to_g4() {
+    use crate::decoder::{decode_g4, pels_packed};
+    let pixels = [0xF0, 0x0F, 0x00];
+    let data = pixels_to_g4(8, 3, &pixels).unwrap();
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), 8, None, |line| decoded.extend(pels_packed(line, 8))).unwrap();
+    assert_eq!(decoded, pixels);
+    assert_eq!(pixels_to_g4(8, 4, &pixels), Err(FaxError::UnexpectedEof));
+    assert_eq!(pixels_to_g4(0, 3, &pixels), Err(FaxError::WidthMismatch));
+}
+
+#[test]
+fn test_encode_g4_empty() {
+    use crate::decoder::decode_g4;
+    let data = encode_g4(std::iter::empty(), 1728);
+    // the EOFB, two EOLs
+    assert_eq!(data, [0x00, 0x10, 0x01]);
+    let mut lines = 0;
+    assert_eq!(decode_g4(data.iter().cloned(), 1728, None, |_| lines += 1), Some(()));
+    assert_eq!(lines, 0);
+}
+
+#[test]
+fn test_encode_g3_empty() {
+    use crate::decoder::{decode_g3_with_options, G3DecodeOptions};
+    let data = encode_g3(std::iter::empty(), 1728);
+    // the leading EOL and the RTC, 7 * 12 bits
+    assert_eq!(data, [0x00, 0x10, 0x01, 0x00, 0x10, 0x01, 0x00, 0x10, 0x01, 0x00, 0x10]);
+    let options = G3DecodeOptions {
+        strict: true,
+        .. G3DecodeOptions::default()
+    };
+    let mut lines = 0;
+    decode_g3_with_options(data.iter().cloned(), &options, |_| lines += 1).unwrap();
+    assert_eq!(lines, 0);
+}
+
+#[test]
+fn test_memory_stats() {
+    let mut encoder = Encoder::new(crate::VecWriter::new());
+    encoder.encode_transitions(&[1, 2, 3, 4], 100);
+    encoder.encode_transitions(&[10, 20], 100);
+    encoder.take_stats();
+    encoder.encode_transitions(&[], 100);
+    let stats = encoder.memory_stats();
+    assert_eq!(stats.peak_transition_count, 4);
+    assert_eq!(stats.lines_encoded, 3);
+    let data = encoder.finish().finish();
+    // the EOFB takes 3 more bytes at most
+    assert!(stats.bytes_written <= data.len() && data.len() <= stats.bytes_written + 3);
+}
+
+/// Terminating codes of T.4 table 2, for white runs of 0 to 63 pixels.
+#[cfg(test)]
+const T4_WHITE_TERMINATING: [&str; 64] = [
+    "00110101", "000111", "0111", "1000", "1011", "1100", "1110", "1111",
+    "10011", "10100", "00111", "01000", "001000", "000011", "110100", "110101",
+    "101010", "101011", "0100111", "0001100", "0001000", "0010111", "0000011", "0000100",
+    "0101000", "0101011", "0010011", "0100100", "0011000", "00000010", "00000011", "00011010",
+    "00011011", "00010010", "00010011", "00010100", "00010101", "00010110", "00010111", "00101000",
+    "00101001", "00101010", "00101011", "00101100", "00101101", "00000100", "00000101", "00001010",
+    "00001011", "01010010", "01010011", "01010100", "01010101", "00100100", "00100101", "01011000",
+    "01011001", "01011010", "01011011", "01001010", "01001011", "00110010", "00110011", "00110100",
+];
+
+/// Terminating codes of T.4 table 2, for black runs of 0 to 63 pixels.
+#[cfg(test)]
+const T4_BLACK_TERMINATING: [&str; 64] = [
+    "0000110111", "010", "11", "10", "011", "0011", "0010", "00011",
+    "000101", "000100", "0000100", "0000101", "0000111", "00000100", "00000111", "000011000",
+    "0000010111", "0000011000", "0000001000", "00001100111", "00001101000", "00001101100", "00000110111", "00000101000",
+    "00000010111", "00000011000", "000011001010", "000011001011", "000011001100", "000011001101", "000001101000", "000001101001",
+    "000001101010", "000001101011", "000011010010", "000011010011", "000011010100", "000011010101", "000011010110", "000011010111",
+    "000001101100", "000001101101", "000011011010", "000011011011", "000001010100", "000001010101", "000001010110", "000001010111",
+    "000001100100", "000001100101", "000001010010", "000001010011", "000000100100", "000000110111", "000000111000", "000000100111",
+    "000000101000", "000001011000", "000001011001", "000000101011", "000000101100", "000001011010", "000001100110", "000001100111",
+];
+
+#[test]
+fn test_terminating_codes() {
+    for (color, table) in [(Color::White, &T4_WHITE_TERMINATING), (Color::Black, &T4_BLACK_TERMINATING)] {
+        for (n, code) in table.iter().enumerate() {
+            let mut writer = crate::VecWriter::new();
+            let mut counted = Counted { writer: &mut writer, bits: 0 };
+            encode_color(&mut counted, color, n as u16);
+            assert_eq!(counted.bits as usize, code.len(), "{:?} {}", color, n);
+
+            // the code, padded with zeros to a byte
+            let mut expected = vec![0; (code.len() + 7) / 8];
+            for (i, bit) in code.bytes().enumerate() {
+                if bit == b'1' {
+                    expected[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+            assert_eq!(writer.finish(), expected, "{:?} {}", color, n);
+        }
+    }
+}
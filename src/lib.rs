@@ -1,5 +1,60 @@
-use std::ops::Not;
-use std::fmt;
+//! Decoder and encoder for the CCITT Group 3 and 4 bi-level image codings, as used by fax machines, TIFF and PDF.
+//! 
+//! Lines are represented as lists of the positions where the color changes, starting with white.
+//! 
+//! # Encoding
+//! 
+//! A packed bitmap (one bit per pixel, `1` is black) can be encoded as Group 4 in one call:
+//! ```
+//! // 16 pixels wide, 2 rows, the left half of the first row is black
+//! let pixels = [0xFF, 0x00, 0x00, 0x00];
+//! let data = fax::encoder::pixels_to_g4(16, 2, &pixels).unwrap();
+//! assert!(!data.is_empty());
+//! ```
+//! 
+//! # Decoding
+//! 
+//! `decoder::g4_to_pixels` and `decoder::g3_to_pixels` return the packed bitmap directly.
+//! For more control, `decoder::decode_g4` and `decoder::decode_g3` call a function for each line,
+//! and `decoder::pels` turns a line into an iterator of pixel colors:
+//! ```
+//! use fax::{Color, decoder::{decode_g4, pels}};
+//! # let data = fax::encoder::pixels_to_g4(16, 2, &[0xFF, 0x00, 0x00, 0x00]).unwrap();
+//! let width = 16;
+//! let mut packed = vec![];
+//! decode_g4(data.iter().cloned(), width, None, |line| {
+//!     let mut byte = 0u8;
+//!     for (i, color) in pels(line, width).enumerate() {
+//!         if color == Color::Black {
+//!             byte |= 0x80 >> (i % 8);
+//!         }
+//!         if i % 8 == 7 {
+//!             packed.push(byte);
+//!             byte = 0;
+//!         }
+//!     }
+//! }).unwrap();
+//! assert_eq!(packed, [0xFF, 0x00, 0x00, 0x00]);
+//! ```
+//! Group 3 data is decoded the same way, without the width.
+//! The last entry of each line is its end, which gives the width:
+//! ```
+//! # let data = fax::encoder::encode_g3([&[8u16][..]].iter().cloned(), 16);
+//! let mut lines = vec![];
+//! fax::decoder::decode_g3(data.iter().cloned(), |line| lines.push(line.to_vec())).unwrap();
+//! assert_eq!(lines, [[8, 16]]);
+//! ```
+//! 
+//! # Without `std`
+//! 
+//! With `default-features = false`, the crate does not depend on `std` or an allocator.
+//! Only `decoder::decode_g4_fixed` and the other decoder items that work on borrowed buffers are available then
+//! (see `examples/embedded.rs`).
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::ops::Not;
+use core::fmt;
 
 mod maps;
 
@@ -7,11 +62,25 @@ mod maps;
 pub mod decoder;
 
 /// Encoder module
+#[cfg(feature = "std")]
 pub mod encoder;
 
 /// TIFF helper functions
+#[cfg(feature = "std")]
 pub mod tiff;
 
+/// Helper functions for lists of color transitions
+#[cfg(feature = "std")]
+pub mod transitions;
+
+/// Utilities for T.30 fax sessions
+#[cfg(feature = "std")]
+pub mod t30;
+
+/// Simulated T.30 sessions for testing
+#[cfg(feature = "std")]
+pub mod sim;
+
 /// Trait used to read data bitwise.
 /// 
 /// For lazy people `ByteReader` is provided which implements this trait.
@@ -45,11 +114,13 @@ pub trait BitReader {
 pub trait BitWriter {
     fn write(&mut self, bits: Bits);
 }
+#[cfg(feature = "std")]
 pub struct VecWriter {
     data: Vec<u8>,
     partial: u32,
     len: u8
 }
+#[cfg(feature = "std")]
 impl BitWriter for VecWriter {
     fn write(&mut self, bits: Bits) {
         self.partial |= (bits.data as u32) << (32 - self.len - bits.len);
@@ -61,6 +132,7 @@ impl BitWriter for VecWriter {
         }
     }
 }
+#[cfg(feature = "std")]
 impl VecWriter {
     pub fn new() -> Self {
         VecWriter {
@@ -87,6 +159,13 @@ impl VecWriter {
         }
     }
 
+    /// Remove and return the complete bytes written so far.
+    /// 
+    /// Bits that do not fill a byte yet are kept.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
     /// pad and return the accumulated bytes
     pub fn finish(mut self) -> Vec<u8> {
         self.pad();
@@ -121,7 +200,7 @@ impl<R: Iterator<Item=u8>> ByteReader<R> {
         }
     }
 }
-impl<'a> ByteReader<std::iter::Cloned<std::slice::Iter<'a, u8>>> {
+impl<'a> ByteReader<core::iter::Cloned<core::slice::Iter<'a, u8>>> {
     /// Construct a new `ByteReader` from a slice of bytes.
     pub fn from_slice(slice: &'a [u8]) -> Self {
         ByteReader::new(slice.iter().cloned())
@@ -132,7 +211,7 @@ impl<'a, R: Iterator<Item=u8> + 'a> ByteReader<R> {
     /// 
     /// Yields one `bool` per bit, `1=true` and `0=false`.
     pub fn into_bits(mut self) -> impl Iterator<Item=bool> + 'a {
-        std::iter::from_fn(move || {
+        core::iter::from_fn(move || {
             let bit = self.peek(1)? == 1;
             self.consume(1);
             Some(bit)
@@ -142,6 +221,7 @@ impl<'a, R: Iterator<Item=u8> + 'a> ByteReader<R> {
     /// Print the remaining data
     /// 
     /// Note: For debug purposes only, not part of the API.
+    #[cfg(feature = "std")]
     pub fn print_remaining(&mut self) {
         println!("partial: {:0w$b}, valid: {}", self.partial & ((1 << self.valid) - 1), self.valid, w=self.valid as usize);
         for b in self.read.by_ref() {
@@ -149,6 +229,7 @@ impl<'a, R: Iterator<Item=u8> + 'a> ByteReader<R> {
         }
         println!();
     }
+    #[cfg(feature = "std")]
     pub fn print_peek(&self) {
         println!("partial: {:0w$b}, valid: {}", self.partial & ((1 << self.valid) - 1), self.valid, w=self.valid as usize);
     }
@@ -195,6 +276,66 @@ impl Not for Color {
     }
 }
 
+#[test]
+fn test_not_color() {
+    assert_eq!(!Color::White, Color::Black);
+    assert_eq!(!Color::Black, Color::White);
+}
+
+/// Coding schemes that are recognized, but can not be decoded.
+/// 
+/// These can not be told apart from supported codings by looking at the coded data,
+/// so they have to be selected by the caller, as given by the container format or the T.30 session
+/// (see `decoder::G3DecodeOptions::coding_scheme`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CodingScheme {
+    /// Simplified two-dimensional coding of ITU-T T.4 Annex C
+    T4AnnexC,
+}
+
+/// Errors that can occur while decoding or converting an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaxError {
+    /// The input ended before the image was complete.
+    UnexpectedEof,
+    /// The input contains a code that is not valid at this point.
+    InvalidCode,
+    /// An extension mode code was found (only reported in strict mode).
+    ExtensionMode,
+    /// A line does not have the expected width (lines that are too short are only reported in strict mode).
+    WidthMismatch,
+    /// The output buffer cannot hold the result.
+    BufferTooSmall,
+    /// The data uses a coding scheme that is not supported.
+    UnsupportedCoding(CodingScheme),
+    /// Reading the input failed.
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+}
+impl fmt::Display for FaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaxError::UnexpectedEof => write!(f, "unexpected end of data"),
+            FaxError::InvalidCode => write!(f, "invalid code"),
+            FaxError::ExtensionMode => write!(f, "extension mode is not supported"),
+            FaxError::WidthMismatch => write!(f, "line does not match the image width"),
+            FaxError::BufferTooSmall => write!(f, "output buffer is too small"),
+            FaxError::UnsupportedCoding(scheme) => write!(f, "unsupported coding scheme {:?}", scheme),
+            #[cfg(feature = "std")]
+            FaxError::Io(kind) => write!(f, "I/O error: {:?}", kind),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for FaxError {}
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FaxError {
+    fn from(e: std::io::Error) -> Self {
+        FaxError::Io(e.kind())
+    }
+}
+
 struct Transitions<'a> {
     edges: &'a [u16],
     pos: usize
@@ -249,12 +390,6 @@ impl<'a> Transitions<'a> {
             None
         }
     }
-    fn peek(&self) -> Option<u16> {
-        self.edges.get(self.pos).cloned()
-    }
-    fn skip(&mut self, n: usize) {
-        self.pos += n;
-    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
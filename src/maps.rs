@@ -10,24 +10,29 @@ impl<T: Copy> Entry<T> {
     fn find(&self, reader: &mut impl BitReader) -> Option<T> {
         match *self {
             Entry::Value(val, len) => {
+                // the code may be longer than the bits looked at so far
+                reader.peek(len)?;
                 reader.consume(len);
                 Some(val)
             }
             Entry::Leaf(width, lut) => {
-                let index = reader.peek(width)?;
+                let index = peek_padded(reader, width)?;
                 let (val, len) = lut[index as usize]?;
+                reader.peek(len)?;
                 reader.consume(len);
                 Some(val)
             }
             Entry::Prefix(width, lut) => {
-                let index = reader.peek(width)?;
+                let index = peek_padded(reader, width)?;
                 let entry = &lut[index as usize];
                 match *entry {
                     Entry::Value(val, len) => {
+                        reader.peek(len)?;
                         reader.consume(len);
                         Some(val)
                     }
                     _ => {
+                        reader.peek(width)?;
                         reader.consume(width);
                         entry.find(reader)
                     }
@@ -37,10 +42,21 @@ impl<T: Copy> Entry<T> {
     }
 }
 
+/// Peek `width` bits, or at the end of the data the bits that are left, padded with zeros.
+///
+/// A short code can be complete even if there are fewer bits left than the table is indexed with.
+fn peek_padded(reader: &impl BitReader, width: u8) -> Option<u16> {
+    reader.peek(width).or_else(|| {
+        (1 .. width).rev().find_map(|n| reader.peek(n).map(|bits| bits << (width - n)))
+    })
+}
+
 pub const EDFB_HALF: Bits = Bits { data: 1, len: 12 };
+#[cfg(feature = "std")]
 pub const EOL: Bits = Bits { data: 1, len: 12 };
 
-#[derive(Copy, Clone, Debug)]
+/// Mode codes of the two-dimensional coding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Mode {
     Pass,
     Horizontal,
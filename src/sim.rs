@@ -0,0 +1,163 @@
+use crate::{VecWriter, decoder::FaxCoding, encoder::{Encoder, G3Encoder}};
+
+/// Width of the simulated pages in pixels (A4).
+pub const PAGE_WIDTH: u16 = 1728;
+
+/// Size of the image data in a facsimile coded data (FCD) frame.
+pub const FCD_FRAME_SIZE: usize = 256;
+
+const ADDRESS: u8 = 0xFF;
+const CONTROL: u8 = 0x03;
+const CONTROL_FINAL: u8 = 0x13;
+
+// facsimile control fields, with the X bit set as by the transmitting station
+const DCS: u8 = 0x83;
+const MPS: u8 = 0x4F;
+const EOP: u8 = 0x2F;
+const FCD: u8 = 0x06;
+const RCP: u8 = 0x86;
+
+/// Data signalling rate of the image transfer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BaudRate {
+    /// V.27 ter, 2400 bit/s
+    V27ter2400,
+    /// V.27 ter, 4800 bit/s
+    V27ter4800,
+    /// V.29, 7200 bit/s
+    V29_7200,
+    /// V.29, 9600 bit/s
+    V29_9600,
+    /// V.17, 14400 bit/s
+    V17_14400,
+}
+
+/// Vertical resolution of the pages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// 3.85 lines/mm
+    Standard,
+    /// 7.7 lines/mm
+    Fine,
+}
+
+/// Generates the HDLC frames a transmitting T.30 station sends for a set of pages.
+///
+/// This is a fixture generator for testing fax stacks, not an implementation of T.30.
+/// The session consists of a DCS frame, then for each page the coded image in FCD frames
+/// (error correction mode is always used), three RCP frames and the post-message command
+/// (MPS, or EOP after the last page).
+/// Pages are not split into partial pages, so frame numbers wrap after 256 frames.
+///
+/// Each frame is returned as the address, control, FCF and FIF fields, without flags and FCS.
+/// The bytes are in transmission order, with the first bit in the least significant bit.
+#[derive(Copy, Clone, Debug)]
+pub struct T30SessionSimulator {
+    pub speed: BaudRate,
+    pub resolution: Resolution,
+    pub coding: FaxCoding,
+}
+impl T30SessionSimulator {
+    /// Frames for sending `pages`, each given as lines of `PAGE_WIDTH` pixels.
+    pub fn frames(&self, pages: &[Vec<Vec<u16>>]) -> Vec<Vec<u8>> {
+        let mut frames = vec![self.dcs()];
+        for (i, page) in pages.iter().enumerate() {
+            let data = self.encode(page);
+            for (n, chunk) in data.chunks(FCD_FRAME_SIZE).enumerate() {
+                let mut frame = vec![ADDRESS, CONTROL, FCD, n as u8];
+                frame.extend(chunk.iter().map(|b| b.reverse_bits()));
+                frames.push(frame);
+            }
+            for _ in 0 .. 3 {
+                frames.push(vec![ADDRESS, CONTROL, RCP]);
+            }
+            let command = if i + 1 == pages.len() { EOP } else { MPS };
+            frames.push(vec![ADDRESS, CONTROL_FINAL, command]);
+        }
+        frames
+    }
+
+    /// The digital command signal, selecting the parameters of the session.
+    fn dcs(&self) -> Vec<u8> {
+        let mut fif = [0u8; 4];
+        // bits as numbered in T.30 table 2, starting at 1
+        let mut set = |bit: usize| fif[(bit - 1) / 8] |= 1 << ((bit - 1) % 8);
+
+        // receiver operation
+        set(10);
+        match self.speed {
+            BaudRate::V27ter2400 => {}
+            BaudRate::V27ter4800 => set(12),
+            BaudRate::V29_7200 => { set(11); set(12); }
+            BaudRate::V29_9600 => set(11),
+            BaudRate::V17_14400 => set(14),
+        }
+        if self.resolution == Resolution::Fine {
+            set(15);
+        }
+        match self.coding {
+            FaxCoding::Mh => {}
+            FaxCoding::Mr => set(16),
+            FaxCoding::Mmr => set(31),
+        }
+        // minimum scan line time of 0 ms
+        set(21);
+        set(22);
+        set(23);
+        // the fourth byte follows
+        set(24);
+        // error correction mode
+        set(27);
+
+        let mut frame = vec![ADDRESS, CONTROL_FINAL, DCS];
+        frame.extend_from_slice(&fif);
+        frame
+    }
+
+    fn encode(&self, page: &[Vec<u16>]) -> Vec<u8> {
+        match self.coding {
+            FaxCoding::Mh | FaxCoding::Mr => {
+                let k = match (self.coding, self.resolution) {
+                    (FaxCoding::Mh, _) => 1,
+                    (_, Resolution::Standard) => 2,
+                    (_, Resolution::Fine) => 4,
+                };
+                let mut encoder = G3Encoder::new(VecWriter::new()).with_k_factor(k);
+                for line in page {
+                    encoder.encode_transitions(line, PAGE_WIDTH);
+                }
+                encoder.finish().finish()
+            }
+            FaxCoding::Mmr => {
+                let mut encoder = Encoder::new(VecWriter::new());
+                for line in page {
+                    encoder.encode_transitions(line, PAGE_WIDTH);
+                }
+                encoder.finish().finish()
+            }
+        }
+    }
+}
+
+#[test]
+fn test_session_frames() {
+    use crate::decoder::decode_g4;
+
+    let page: Vec<Vec<u16>> = (0 .. 500).map(|y| vec![y, y + 100, 1000]).collect();
+    let sim = T30SessionSimulator {
+        speed: BaudRate::V17_14400,
+        resolution: Resolution::Fine,
+        coding: FaxCoding::Mmr,
+    };
+    let frames = sim.frames(&[page.clone(), page.clone()]);
+    assert_eq!(frames[0], [ADDRESS, CONTROL_FINAL, DCS, 0x00, 0x62, 0xF0, 0x44]);
+
+    let fcd: Vec<_> = frames.iter().take_while(|f| f[2] != RCP).filter(|f| f[2] == FCD).collect();
+    let data: Vec<u8> = fcd.iter().flat_map(|f| f[4 ..].iter().map(|b| b.reverse_bits())).collect();
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), PAGE_WIDTH, None, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, page);
+
+    let commands: Vec<u8> = frames.iter().filter(|f| f[1] == CONTROL_FINAL).map(|f| f[2]).collect();
+    assert_eq!(commands, [DCS, MPS, EOP]);
+}
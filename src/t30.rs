@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Maximum number of characters in a sub-address.
+pub const SUB_ADDRESS_MAX_LEN: usize = 20;
+
+/// Errors from parsing a `SubAddress`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubAddressError {
+    /// More than 20 characters.
+    TooLong,
+    /// The character is not allowed in a sub-address.
+    InvalidCharacter(char),
+}
+impl fmt::Display for SubAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubAddressError::TooLong => write!(f, "sub-address is longer than {} characters", SUB_ADDRESS_MAX_LEN),
+            SubAddressError::InvalidCharacter(c) => write!(f, "invalid character {:?} in sub-address", c),
+        }
+    }
+}
+impl std::error::Error for SubAddressError {}
+
+/// The sub-address (SUB) of a T.30 session, identifying the recipient behind a fax terminal.
+/// 
+/// It consists of up to 20 T.50 characters out of the digits `0`-`9`, `*`, `#` and space.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubAddress(String);
+
+impl SubAddress {
+    /// Check that `s` is a valid sub-address.
+    pub fn parse(s: &str) -> Result<SubAddress, SubAddressError> {
+        if let Some(c) = s.chars().find(|&c| !matches!(c, '0' ..= '9' | '*' | '#' | ' ')) {
+            return Err(SubAddressError::InvalidCharacter(c));
+        }
+        // all valid characters are ASCII
+        if s.len() > SUB_ADDRESS_MAX_LEN {
+            return Err(SubAddressError::TooLong);
+        }
+        Ok(SubAddress(s.into()))
+    }
+
+    /// The sub-address as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+impl fmt::Display for SubAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Update the HDLC frame check sequence (CRC-16 as of ITU-T V.42) with `byte`.
+fn fcs_update(fcs: u16, byte: u8) -> u16 {
+    let mut fcs = fcs ^ byte as u16;
+    for _ in 0 .. 8 {
+        fcs = if fcs & 1 == 1 { (fcs >> 1) ^ 0x8408 } else { fcs >> 1 };
+    }
+    fcs
+}
+/// FCS over a frame including its own FCS
+const FCS_GOOD: u16 = 0xF0B8;
+
+/// Strips the HDLC framing from a T.30 data stream.
+/// 
+/// The input is the raw bit stream, with the first received bit in the least significant bit of each byte.
+/// Flags are removed, the payload is un-bit-stuffed and frames with a wrong FCS are dropped.
+/// The frame contents are yielded with the first received bit in the most significant bit,
+/// which is the order expected by the decoders:
+/// 
+/// `decode_g3(HdlcUnstuffer::new(raw_bytes), line_cb)`
+pub struct HdlcUnstuffer<R> {
+    input: R,
+    header_len: usize,
+    /// a flag was seen, so we are inside a frame
+    in_frame: bool,
+    /// number of consecutive `1` bits
+    ones: u8,
+    frame: Vec<u8>,
+    partial: u8,
+    partial_bits: u8,
+    output: VecDeque<u8>,
+    fcs_errors: usize,
+}
+impl<R: Iterator<Item=u8>> HdlcUnstuffer<R> {
+    pub fn new(input: R) -> Self {
+        HdlcUnstuffer {
+            input,
+            header_len: 0,
+            in_frame: false,
+            ones: 0,
+            frame: vec![],
+            partial: 0,
+            partial_bits: 0,
+            output: VecDeque::new(),
+            fcs_errors: 0,
+        }
+    }
+
+    /// Drop the first `n` bytes of each frame.
+    /// 
+    /// For T.30 facsimile coded data (FCD) frames, the address, control, FCF and frame number fields take 4 bytes.
+    pub fn with_header_len(self, n: usize) -> Self {
+        HdlcUnstuffer { header_len: n, .. self }
+    }
+
+    /// Number of frames dropped so far, because of a wrong FCS or a length that is not a multiple of 8 bits.
+    pub fn fcs_errors(&self) -> usize {
+        self.fcs_errors
+    }
+
+    fn push_bit(&mut self, bit: u8) {
+        self.partial |= bit << self.partial_bits;
+        self.partial_bits += 1;
+        if self.partial_bits == 8 {
+            self.frame.push(self.partial);
+            self.partial = 0;
+            self.partial_bits = 0;
+        }
+    }
+
+    fn end_frame(&mut self) {
+        // the zero and five ones of the flag have been added already
+        if self.frame.is_empty() {
+            // back-to-back flags
+        } else if self.partial_bits != 6 {
+            self.fcs_errors += 1;
+        } else {
+            let fcs = self.frame.iter().fold(0xFFFF, |fcs, &b| fcs_update(fcs, b));
+            if self.frame.len() < 2 || fcs != FCS_GOOD {
+                self.fcs_errors += 1;
+            } else {
+                let end = self.frame.len() - 2;
+                let start = self.header_len.min(end);
+                self.output.extend(self.frame[start .. end].iter().map(|b| b.reverse_bits()));
+            }
+        }
+        self.frame.clear();
+        self.partial = 0;
+        self.partial_bits = 0;
+    }
+
+    fn process_bit(&mut self, bit: u8) {
+        if bit == 1 {
+            self.ones += 1;
+            match self.ones {
+                // possibly a flag
+                6 => {}
+                // abort sequence
+                7 => {
+                    self.in_frame = false;
+                    self.frame.clear();
+                    self.partial = 0;
+                    self.partial_bits = 0;
+                }
+                8 ..= u8::MAX => self.ones = 7,
+                _ if self.in_frame => self.push_bit(1),
+                _ => {}
+            }
+        } else {
+            match self.ones {
+                // stuffed bit
+                5 => {}
+                // flag
+                6 => {
+                    if self.in_frame {
+                        self.end_frame();
+                    }
+                    self.in_frame = true;
+                }
+                _ if self.in_frame => self.push_bit(0),
+                _ => {}
+            }
+            self.ones = 0;
+        }
+    }
+}
+impl<R: Iterator<Item=u8>> Iterator for HdlcUnstuffer<R> {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        while self.output.is_empty() {
+            let byte = self.input.next()?;
+            for i in 0 .. 8 {
+                self.process_bit((byte >> i) & 1);
+            }
+        }
+        self.output.pop_front()
+    }
+}
+
+#[test]
+fn test_sub_address() {
+    assert_eq!(SubAddress::parse("1234 *#").unwrap().as_str(), "1234 *#");
+    assert_eq!(SubAddress::parse("12a"), Err(SubAddressError::InvalidCharacter('a')));
+    assert_eq!(SubAddress::parse("123456789012345678901"), Err(SubAddressError::TooLong));
+}
+
+#[test]
+fn test_hdlc_unstuffer() {
+    // bit-stuff a frame with a lot of ones, least significant bit first
+    let payload = [0xFF, 0x03, 0x7E, 0x12];
+    let fcs = !payload.iter().fold(0xFFFF, |fcs, &b| fcs_update(fcs, b));
+    let mut bits = vec![0, 1, 1, 1, 1, 1, 1, 0];
+    let mut ones = 0;
+    for &byte in payload.iter().chain(fcs.to_le_bytes().iter()) {
+        for i in 0 .. 8 {
+            let bit = (byte >> i) & 1;
+            bits.push(bit);
+            ones = if bit == 1 { ones + 1 } else { 0 };
+            if ones == 5 {
+                bits.push(0);
+                ones = 0;
+            }
+        }
+    }
+    bits.extend_from_slice(&[0, 1, 1, 1, 1, 1, 1, 0]);
+    let raw: Vec<u8> = bits.chunks(8).map(|c| c.iter().enumerate().map(|(i, &b)| b << i).sum()).collect();
+
+    let mut unstuffer = HdlcUnstuffer::new(raw.iter().cloned()).with_header_len(2);
+    let data: Vec<u8> = unstuffer.by_ref().collect();
+    assert_eq!(data, [0x7E, 0x48]);
+    assert_eq!(unstuffer.fcs_errors(), 0);
+}
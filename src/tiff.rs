@@ -1,3 +1,6 @@
+use crate::{FaxError, VecWriter, encoder::Encoder, decoder::{decode_g3_with_options, decode_g4_with_options, decode_g4_with_reference, G3DecodeOptions, G4DecodeOptions, FaxCoding}};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
 #[derive(Copy, Clone)]
 enum Value {
     Short(u16),
@@ -71,3 +74,287 @@ pub fn wrap(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     out.extend_from_slice(data);
     out
 }
+
+/// Formats of fax content, as told apart by `detect_fax_format`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaxFormat {
+    /// Bare Group 3 data starting with an EOL (`image/g3fax`, RFC 1494)
+    G3Fax,
+    /// TIFF with fax compression (`image/tiff-fx`, RFC 3949)
+    TiffFx { compression: FaxCoding },
+    /// Neither of the above
+    Unknown,
+}
+
+/// Returns `true` if the data starts with an EOL, possibly after fill bits.
+fn starts_with_eol(data: &[u8]) -> bool {
+    match data.get(.. 4) {
+        Some(&[a, b, c, d]) => (11 .. 32).contains(&u32::from_be_bytes([a, b, c, d]).leading_zeros()),
+        _ => false,
+    }
+}
+
+/// Tell bare Group 3 data and fax TIFF files apart.
+/// 
+/// For TIFF, the `Compression` and `T4Options` tags of the first IFD select the coding.
+/// TIFF files with other compressions are `FaxFormat::Unknown`.
+pub fn detect_fax_format(data: &[u8]) -> FaxFormat {
+    let little_endian = match data.get(.. 4) {
+        Some(&[0x49, 0x49, 42, 0]) => true,
+        Some(&[0x4D, 0x4D, 0, 42]) => false,
+        Some(_) => return if starts_with_eol(data) { FaxFormat::G3Fax } else { FaxFormat::Unknown },
+        _ => return FaxFormat::Unknown,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let bytes = [*data.get(pos)?, *data.get(pos + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let bytes = [*data.get(pos)?, *data.get(pos + 1)?, *data.get(pos + 2)?, *data.get(pos + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    let find_tags = || -> Option<(u16, u32)> {
+        let ifd = u32_at(4)? as usize;
+        let mut compression = 1;
+        let mut t4_options = 0;
+        for i in 0 .. u16_at(ifd)? as usize {
+            let entry = ifd + 2 + 12 * i;
+            match u16_at(entry)? {
+                259 => compression = u16_at(entry + 8)?,
+                292 => t4_options = u32_at(entry + 8)?,
+                _ => {}
+            }
+        }
+        Some((compression, t4_options))
+    };
+    match find_tags() {
+        Some((3, t4_options)) if t4_options & 1 == 1 => FaxFormat::TiffFx { compression: FaxCoding::Mr },
+        Some((3, _)) => FaxFormat::TiffFx { compression: FaxCoding::Mh },
+        Some((4, _)) => FaxFormat::TiffFx { compression: FaxCoding::Mmr },
+        _ => FaxFormat::Unknown,
+    }
+}
+
+/// Coding parameters of a fax compressed TIFF image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TiffFaxParams {
+    /// Negative for Group 4, `0` for one-dimensional and positive for two-dimensional Group 3, as in PDF.
+    pub k: i32,
+    /// Each coded line starts at a byte boundary.
+    pub encoded_byte_align: bool,
+}
+impl TiffFaxParams {
+    /// The parameters for the values of the `Compression` and `T4Options` tags.
+    /// 
+    /// Returns `None` if `compression` is not one of the CCITT compressions (2, 3 and 4).
+    pub fn from_tags(compression: u16, t4_options: u32) -> Option<TiffFaxParams> {
+        match compression {
+            // Modified Huffman without EOLs, each row starting at a byte
+            2 => Some(TiffFaxParams { k: 0, encoded_byte_align: true }),
+            3 => Some(TiffFaxParams {
+                k: (t4_options & 1) as i32,
+                encoded_byte_align: t4_options & 4 != 0,
+            }),
+            4 => Some(TiffFaxParams { k: -1, encoded_byte_align: false }),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes fax compressed TIFF strips with the coding given by `params`.
+#[derive(Copy, Clone, Debug)]
+pub struct TiffDecoder {
+    pub params: TiffFaxParams,
+}
+impl TiffDecoder {
+    /// Decode a strip of `height` lines.
+    /// 
+    /// Returns `FaxError::UnexpectedEof` if the data holds less than `height` lines.
+    /// Group 3 lines are reported like Group 4 ones, without the end of the line (see `G3DecodeOptions::omit_line_end`).
+    pub fn decode(&self, data: &[u8], width: u16, height: u32, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+        let mut lines = 0;
+        let mut counted = |line: &[u16]| {
+            if lines < height {
+                line_cb(line);
+            }
+            lines += 1;
+        };
+        if self.params.k < 0 {
+            let options = G4DecodeOptions {
+                max_lines: Some(height),
+                encoded_byte_align: self.params.encoded_byte_align,
+                .. G4DecodeOptions::default()
+            };
+            decode_g4_with_options(data.iter().cloned(), width, &options, &mut counted)?;
+        } else {
+            let options = G3DecodeOptions {
+                width: Some(width),
+                encoded_byte_align: self.params.encoded_byte_align,
+                two_dimensional: self.params.k > 0,
+                // not all writers start the strip with an EOL
+                leading_eol: starts_with_eol(data),
+                omit_line_end: true,
+                .. G3DecodeOptions::default()
+            };
+            decode_g3_with_options(data.iter().cloned(), &options, &mut counted)?;
+        }
+        if lines < height {
+            return Err(FaxError::UnexpectedEof);
+        }
+        Ok(())
+    }
+}
+
+/// Decode a single Group 4 coded TIFF strip of `height` lines.
+/// 
+/// The first line is coded relative to an all-white line.
+/// Returns the lines as lists of color changes, or `FaxError::UnexpectedEof`
+/// if the strip holds less than `height` lines.
+pub fn decode_g4_strip(strip_data: &[u8], width: u16, height: u32) -> Result<Vec<Vec<u16>>, FaxError> {
+    decode_g4_strip_with_ref(strip_data, width, height, &[])
+}
+
+/// Decode a single Group 4 coded TIFF strip, where the first line is coded relative to `reference`.
+/// 
+/// This is for TIFF writers that carry the reference line across strip boundaries,
+/// in which case `reference` is the last line of the previous strip.
+pub fn decode_g4_strip_with_ref(strip_data: &[u8], width: u16, height: u32, reference: &[u16]) -> Result<Vec<Vec<u16>>, FaxError> {
+    let mut lines = Vec::with_capacity(height as usize);
+    decode_g4_with_reference(strip_data.iter().cloned(), width, Some(height), reference, |line| lines.push(line.to_vec()))?;
+    if lines.len() < height as usize {
+        return Err(FaxError::UnexpectedEof);
+    }
+    Ok(lines)
+}
+
+/// Encode the lines of a single TIFF strip as Group 4, ending with the EOFB.
+/// 
+/// The first line is coded relative to an all-white line, the counterpart of `decode_g4_strip`.
+pub fn encode_g4_strip(lines: &[&[u16]], width: u16) -> Vec<u8> {
+    encode_g4_strip_with_ref(lines, width, &[])
+}
+
+/// Encode the lines of a single TIFF strip as Group 4, where the first line is coded relative to `initial_ref`.
+/// 
+/// The counterpart of `decode_g4_strip_with_ref`.
+pub fn encode_g4_strip_with_ref(lines: &[&[u16]], width: u16, initial_ref: &[u16]) -> Vec<u8> {
+    let mut encoder = Encoder::with_reference(VecWriter::new(), initial_ref);
+    for line in lines {
+        encoder.encode_transitions(line, width);
+    }
+    encoder.finish().finish()
+}
+
+/// Join the lines of decoded strips (as returned by `decode_g4_strip`) into the lines of the page.
+/// 
+/// Color changes are positions within a line, so they need no adjustment.
+pub fn concat_strips(strips: &[Vec<Vec<u16>>]) -> Vec<Vec<u16>> {
+    strips.iter().flatten().cloned().collect()
+}
+
+/// Decode a Group 4 coded TIFF image with several strips from `reader`.
+/// 
+/// `strip_offsets` and `strip_byte_counts` are the values of the corresponding TIFF tags,
+/// `lines_per_strip` is the value of `RowsPerStrip`. Each strip starts with an all-white reference line.
+/// The callback `line_cb` is called for each decoded line, as in `decode_g4`.
+/// 
+/// Tags of different lengths are reported as `FaxError::Io(ErrorKind::InvalidInput)`.
+pub fn decode_g4_seekable(reader: &mut (impl Read + Seek), strip_offsets: &[u64], strip_byte_counts: &[u32], width: u16, lines_per_strip: u32, mut line_cb: impl FnMut(&[u16])) -> Result<(), FaxError> {
+    let options = G4DecodeOptions {
+        max_lines: Some(lines_per_strip),
+        .. G4DecodeOptions::default()
+    };
+    if strip_offsets.len() != strip_byte_counts.len() {
+        return Err(FaxError::Io(ErrorKind::InvalidInput));
+    }
+    let mut data = vec![];
+    for (&offset, &len) in strip_offsets.iter().zip(strip_byte_counts) {
+        reader.seek(SeekFrom::Start(offset))?;
+        data.resize(len as usize, 0);
+        reader.read_exact(&mut data)?;
+        decode_g4_with_options(data.iter().cloned(), width, &options, &mut line_cb)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_concat_strips() {
+    let strips = vec![vec![vec![1, 2], vec![]], vec![vec![3]]];
+    assert_eq!(concat_strips(&strips), vec![vec![1, 2], vec![], vec![3]]);
+    assert!(concat_strips(&[]).is_empty());
+}
+
+#[test]
+fn test_detect_fax_format() {
+    assert_eq!(detect_fax_format(&wrap(&[0; 4], 8, 1)), FaxFormat::TiffFx { compression: FaxCoding::Mmr });
+    assert_eq!(detect_fax_format(&[0x00, 0x14, 0xD9, 0xA8]), FaxFormat::G3Fax);
+    assert_eq!(detect_fax_format(b"%PDF"), FaxFormat::Unknown);
+    assert_eq!(detect_fax_format(&[0x49, 0x49, 42, 0, 0xFF]), FaxFormat::Unknown);
+}
+
+#[test]
+fn test_tiff_decoder() {
+    use crate::encoder::{encode_1d, encode_g3, encode_g4, G3Encoder};
+    let lines = [vec![], vec![3, 40], vec![0, 10]];
+    // Compression=2: no EOLs, each line starts at a byte
+    let mut writer = VecWriter::new();
+    for line in &lines {
+        encode_1d(&mut writer, line, 64);
+        writer.pad();
+    }
+    let mh = writer.finish();
+    let g3 = encode_g3(lines.iter().map(|l| l.as_slice()), 64);
+    let mut encoder = G3Encoder::new(VecWriter::new()).with_k_factor(2);
+    for line in &lines {
+        encoder.encode_transitions(line, 64);
+    }
+    let g3_2d = encoder.finish().finish();
+    let g4 = encode_g4(lines.iter().map(|l| l.as_slice()), 64);
+    for (compression, t4_options, data) in [(2, 0, &mh), (3, 0, &g3), (3, 1, &g3_2d), (4, 0, &g4)] {
+        let decoder = TiffDecoder { params: TiffFaxParams::from_tags(compression, t4_options).unwrap() };
+        let mut decoded = vec![];
+        decoder.decode(data, 64, 3, |line| decoded.push(line.to_vec())).unwrap();
+        assert_eq!(decoded, lines);
+        assert_eq!(decoder.decode(data, 64, 4, |_| ()), Err(FaxError::UnexpectedEof));
+    }
+}
+
+#[test]
+fn test_decode_g4_seekable() {
+    use std::io::Cursor;
+    let page: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![y, 20 + y, 50]).collect();
+    let (first, second) = page.split_at(5);
+    let first_strip = encode_g4_strip(&first.iter().map(|l| l.as_slice()).collect::<Vec<_>>(), 64);
+    let second_strip = encode_g4_strip(&second.iter().map(|l| l.as_slice()).collect::<Vec<_>>(), 64);
+
+    // the second strip is stored before the first one, with a gap between them
+    let mut file = vec![0xAA; 8];
+    file.extend_from_slice(&second_strip);
+    file.extend_from_slice(&[0xAA; 5]);
+    let first_offset = file.len() as u64;
+    file.extend_from_slice(&first_strip);
+
+    let offsets = [first_offset, 8];
+    let byte_counts = [first_strip.len() as u32, second_strip.len() as u32];
+    let mut decoded = vec![];
+    decode_g4_seekable(&mut Cursor::new(&file), &offsets, &byte_counts, 64, 5, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, page);
+
+    assert_eq!(
+        decode_g4_seekable(&mut Cursor::new(&file), &offsets, &byte_counts[.. 1], 64, 5, |_| ()),
+        Err(FaxError::Io(ErrorKind::InvalidInput))
+    );
+}
+
+#[test]
+fn test_encode_g4_strip() {
+    let page: Vec<Vec<u16>> = (0 .. 10).map(|y| vec![y, 20 + y, 50]).collect();
+    let page: Vec<&[u16]> = page.iter().map(|line| line.as_slice()).collect();
+    let (first, second) = page.split_at(4);
+
+    let strip = encode_g4_strip(first, 64);
+    assert_eq!(decode_g4_strip(&strip, 64, 4).unwrap(), first);
+
+    let strip = encode_g4_strip_with_ref(second, 64, first[3]);
+    assert_eq!(decode_g4_strip_with_ref(&strip, 64, 6, first[3]).unwrap(), second);
+}
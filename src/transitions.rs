@@ -0,0 +1,565 @@
+use crate::Color;
+use crate::encoder::{run_codes, color_code};
+
+/// Number of runs in the line.
+/// 
+/// This is one more than the number of color changes.
+/// Note that a line starting with black has a leading white run of length zero, which is counted as well.
+pub fn count(line: &[u16]) -> usize {
+    line.len() + 1
+}
+
+/// Returns `true` if the line is entirely white.
+pub fn is_blank(line: &[u16]) -> bool {
+    line.is_empty()
+}
+
+/// The color of the pixel at `position`.
+/// 
+/// This does a binary search on the transitions, so it takes `O(log n)` time.
+pub fn color_at(line: &[u16], position: u16) -> Color {
+    match line.partition_point(|&p| p <= position) % 2 {
+        0 => Color::White,
+        _ => Color::Black
+    }
+}
+
+/// The color changes of a line of pixels, where `true` is black if `black_is_true` is set, otherwise white.
+pub fn from_bool_slice(pixels: &[bool], black_is_true: bool) -> Vec<u16> {
+    let mut line = vec![];
+    let mut black = false;
+    for (i, &p) in pixels.iter().enumerate() {
+        if (p == black_is_true) != black {
+            line.push(i as u16);
+            black = !black;
+        }
+    }
+    line
+}
+
+/// The color changes of a row of packed pixels, one bit per pixel with `1` for black, as produced by `decoder::pels_packed`.
+/// 
+/// Bits after `width` are ignored.
+pub fn packed_pixels_to_transitions(row: &[u8], width: u16) -> Vec<u16> {
+    let mut line = vec![];
+    let mut black = false;
+    for i in 0 .. width as usize {
+        let p = row[i / 8] & (0x80 >> (i % 8)) != 0;
+        if p != black {
+            line.push(i as u16);
+            black = p;
+        }
+    }
+    line
+}
+
+/// Number of all-white lines at the top and at the bottom of the image.
+/// 
+/// Transitions at or beyond `width` are ignored.
+/// If the whole image is white, all lines are counted as top margin.
+pub fn detect_margins(lines: &[&[u16]], width: u16) -> (u32, u32) {
+    let blank = |line: &&&[u16]| match line.first() {
+        Some(&p) => p >= width,
+        None => true
+    };
+    let top = lines.iter().take_while(blank).count();
+    let bottom = lines[top ..].iter().rev().take_while(blank).count();
+    (top as u32, bottom as u32)
+}
+
+/// Remove the white lines at the top and at the bottom of the image.
+pub fn crop_margins(mut lines: Vec<Vec<u16>>, width: u16) -> Vec<Vec<u16>> {
+    let (top, bottom) = {
+        let refs: Vec<&[u16]> = lines.iter().map(|l| l.as_slice()).collect();
+        detect_margins(&refs, width)
+    };
+    lines.truncate(lines.len() - bottom as usize);
+    lines.drain(.. top as usize);
+    lines
+}
+
+/// The position where the last run of the line ends.
+/// 
+/// For a valid line (no transition beyond `width`) this is `width`.
+/// Otherwise the position of the last transition is returned.
+pub fn last_run_end(line: &[u16], width: u16) -> u16 {
+    match line.last() {
+        Some(&p) if p > width => p,
+        _ => width
+    }
+}
+
+/// Returns `true` if the runs of the line add up to exactly `width`.
+pub fn is_full_width(line: &[u16], width: u16) -> bool {
+    last_run_end(line, width) == width
+}
+
+/// Add a color change at `p`, which must not be before the last one.
+/// 
+/// Two changes at the same position cancel out.
+fn push_change(line: &mut Vec<u16>, p: u16) {
+    if line.last() == Some(&p) {
+        line.pop();
+    } else {
+        line.push(p);
+    }
+}
+
+/// Split a line at pixel column `split_at` into two independent lines.
+/// 
+/// Positions in the right half start at `0` again. If the right half starts with black,
+/// it begins with a transition at `0`, as usual.
+pub fn split_columns(line: &[u16], split_at: u16) -> (Vec<u16>, Vec<u16>) {
+    let n = line.iter().take_while(|&&p| p < split_at).count();
+    let left = line[.. n].to_vec();
+    let mut right = vec![];
+    if n % 2 == 1 {
+        right.push(0);
+    }
+    for &p in &line[n ..] {
+        push_change(&mut right, p - split_at);
+    }
+    (left, right)
+}
+
+/// Extract the columns `left .. right` of a line.
+/// 
+/// Positions are relative to `left`. If `left` is inside a black run, the result begins with
+/// a transition at `0`. Changes at or beyond `right` are dropped, as the result is `right - left` wide.
+pub fn crop_transitions(line: &[u16], left: u16, right: u16) -> Vec<u16> {
+    let n = line.iter().take_while(|&&p| p < left).count();
+    let mut cropped = vec![];
+    if n % 2 == 1 {
+        cropped.push(0);
+    }
+    for &p in line[n ..].iter().take_while(|&&p| p < right) {
+        push_change(&mut cropped, p - left);
+    }
+    cropped
+}
+
+/// Mirror a line horizontally, so the first run becomes the last.
+/// 
+/// A change at `p` moves to `width - p`. If the line ends black, the mirrored line starts black,
+/// so it begins with a transition at `0`.
+pub fn reverse_transitions(line: &[u16], width: u16) -> Vec<u16> {
+    let n = line.iter().take_while(|&&p| p < width).count();
+    let mut reversed = Vec::with_capacity(n + 1);
+    if n % 2 == 1 {
+        reversed.push(0);
+    }
+    // a change at 0 would end up at the width
+    reversed.extend(line[.. n].iter().rev().filter(|&&p| p > 0).map(|&p| width - p));
+    reversed
+}
+
+/// Join two lines side by side, where the left one is `left_width` pixels wide.
+/// 
+/// This is the inverse of `split_columns`. Runs of the same color meeting at the join are merged.
+pub fn merge_columns(left: &[u16], right: &[u16], left_width: u16) -> Vec<u16> {
+    let mut line: Vec<u16> = left.iter().cloned().take_while(|&p| p < left_width).collect();
+    // back to white, which the right line starts with
+    if line.len() % 2 == 1 {
+        line.push(left_width);
+    }
+    for &p in right {
+        push_change(&mut line, p + left_width);
+    }
+    line
+}
+
+/// Alias of `merge_columns`, for combining two half-width lines.
+pub use self::merge_columns as merge_lines;
+
+/// Remove zero-length runs from the line.
+/// 
+/// Two changes at the same position cancel out, so the pixels are the same as before.
+pub fn normalize_transitions(line: &[u16]) -> Vec<u16> {
+    let mut out = Vec::with_capacity(line.len());
+    for &p in line {
+        push_change(&mut out, p);
+    }
+    out
+}
+
+/// Treat repeated changes at the same position as a single one.
+/// 
+/// Unlike `normalize_transitions`, this changes the pixels: it is for lists where
+/// a change was recorded twice, not for zero-length runs.
+pub fn deduplicate_transitions(mut line: Vec<u16>) -> Vec<u16> {
+    line.dedup();
+    line
+}
+
+/// Invert the colors of the line, for example for images where white is `0` (`PhotometricInterpretation = 0` in TIFF).
+/// 
+/// The positions of the changes stay the same, only the starting color flips:
+/// a leading change at `0` is removed, otherwise one is added.
+pub fn invert_transitions(line: &[u16]) -> Vec<u16> {
+    match line.split_first() {
+        Some((0, rest)) => rest.to_vec(),
+        _ => std::iter::once(0).chain(line.iter().cloned()).collect(),
+    }
+}
+
+/// Pixels that differ between `a` and `b` are black in the result.
+/// 
+/// As the color of a pixel flips with every transition of either line,
+/// the result consists of the transitions of both lines, where transitions at the same position cancel out.
+pub fn xor_lines(a: &[u16], b: &[u16], width: u16) -> Vec<u16> {
+    let mut line = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    loop {
+        let p = match (a.peek(), b.peek()) {
+            (Some(&&pa), Some(&&pb)) if pa <= pb => a.next(),
+            (Some(_), Some(_)) => b.next(),
+            (Some(_), None) => a.next(),
+            (None, Some(_)) => b.next(),
+            (None, None) => break
+        };
+        match p {
+            Some(&p) if p < width => push_change(&mut line, p),
+            _ => break
+        }
+    }
+    line
+}
+
+/// Reconstruct a line from `base` and the `delta` obtained by `xor_lines(base, line, width)`.
+pub fn apply_xor(base: &[u16], delta: &[u16], width: u16) -> Vec<u16> {
+    xor_lines(base, delta, width)
+}
+
+/// Pixels that have the same color in `a` and `b` are black in the result.
+/// 
+/// This is the complement of `xor_lines`.
+pub fn line_intersection(a: &[u16], b: &[u16], width: u16) -> Vec<u16> {
+    let mut line = vec![0];
+    for p in xor_lines(a, b, width) {
+        push_change(&mut line, p);
+    }
+    if width == 0 {
+        line.clear();
+    }
+    line
+}
+
+/// Number of pixels that differ between `a` and `b`.
+pub fn line_difference_count(a: &[u16], b: &[u16], width: u16) -> u32 {
+    run_pairs(&xor_lines(a, b, width), width)
+        .filter(|&(color, _)| color == Color::Black)
+        .map(|(_, n)| n as u32)
+        .sum()
+}
+
+/// The runs where `actual` differs from `expected`, as `(start, end, expected_color, actual_color)`.
+/// 
+/// `end` is exclusive. Adjacent differing pixels with the same pair of colors form one run.
+/// Meant for finding the cause of a mismatch in decoded lines.
+pub fn diff_lines(expected: &[u16], actual: &[u16], width: u16) -> Vec<(u16, u16, Color, Color)> {
+    let color = |n: usize| match n % 2 {
+        0 => Color::White,
+        _ => Color::Black
+    };
+    let mut diffs: Vec<(u16, u16, Color, Color)> = vec![];
+    let (mut i, mut j) = (0, 0);
+    let mut start = 0;
+    while start < width {
+        while i < expected.len() && expected[i] <= start {
+            i += 1;
+        }
+        while j < actual.len() && actual[j] <= start {
+            j += 1;
+        }
+        let next_e = expected.get(i).cloned().unwrap_or(width);
+        let next_a = actual.get(j).cloned().unwrap_or(width);
+        let end = next_e.min(next_a).min(width);
+        let (e, a) = (color(i), color(j));
+        if e != a {
+            match diffs.last_mut() {
+                Some(last) if last.1 == start && last.2 == e => last.1 = end,
+                _ => diffs.push((start, end, e, a)),
+            }
+        }
+        start = end;
+    }
+    diffs
+}
+
+/// Iterate over the runs of a line as `(color, length)` pairs.
+/// 
+/// The first run is always white, so a line starting with black yields a white run of length zero first.
+/// The last run extends to `width`.
+pub fn run_pairs(line: &[u16], width: u16) -> impl Iterator<Item=(Color, u16)> + '_ {
+    let mut color = Color::White;
+    let mut last = 0;
+    line.iter().cloned().chain(std::iter::once(width)).map(move |p| {
+        let run = (color, p.saturating_sub(last));
+        color = !color;
+        last = last.max(p);
+        run
+    })
+}
+
+/// Number of runs of `color` in the line.
+/// 
+/// Runs of length zero (the white run in front of a line starting with black) are not counted.
+pub fn count_color(line: &[u16], color: Color) -> u32 {
+    // the last run is not empty for any width beyond the last change
+    run_pairs(line, u16::MAX).filter(|&(c, n)| c == color && n > 0).count() as u32
+}
+
+/// Number of pixels of `color` in the line.
+pub fn total_pixels_of_color(line: &[u16], width: u16, color: Color) -> u32 {
+    run_pairs(line, width).filter(|&(c, _)| c == color).map(|(_, n)| n as u32).sum()
+}
+
+/// Statistics of the run lengths in an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunHistogram {
+    /// Number of white terminating codes for each run length 0-63
+    pub white_runs: [u32; 64],
+    /// Number of white make-up codes as `(run length, count)`, sorted by run length
+    pub white_makeup: Vec<(u16, u32)>,
+    /// Number of black terminating codes for each run length 0-63
+    pub black_runs: [u32; 64],
+    /// Number of black make-up codes as `(run length, count)`, sorted by run length
+    pub black_makeup: Vec<(u16, u32)>,
+}
+impl RunHistogram {
+    fn new() -> Self {
+        RunHistogram {
+            white_runs: [0; 64],
+            white_makeup: vec![],
+            black_runs: [0; 64],
+            black_makeup: vec![],
+        }
+    }
+    fn add(&mut self, color: Color, run: u16) {
+        let (runs, makeup) = match color {
+            Color::White => (&mut self.white_runs, &mut self.white_makeup),
+            Color::Black => (&mut self.black_runs, &mut self.black_makeup),
+        };
+        for code in run_codes(run) {
+            if code < 64 {
+                runs[code as usize] += 1;
+            } else {
+                match makeup.binary_search_by_key(&code, |&(n, _)| n) {
+                    Ok(idx) => makeup[idx].1 += 1,
+                    Err(idx) => makeup.insert(idx, (code, 1)),
+                }
+            }
+        }
+    }
+
+    /// Estimate the number of bits per pixel when the image is encoded one-dimensionally (Modified Huffman).
+    /// 
+    /// Only the run length codes are taken into account, not EOLs or fill bits.
+    pub fn compression_ratio_estimate(&self) -> f32 {
+        let mut bits = 0u64;
+        let mut pixels = 0u64;
+        for &(color, runs, makeup) in [
+            (Color::White, &self.white_runs, &self.white_makeup),
+            (Color::Black, &self.black_runs, &self.black_makeup),
+        ].iter() {
+            let terminating = runs.iter().enumerate().map(|(n, &count)| (n as u16, count));
+            for (n, count) in terminating.chain(makeup.iter().cloned()) {
+                bits += color_code(color, n).len as u64 * count as u64;
+                pixels += n as u64 * count as u64;
+            }
+        }
+        if pixels == 0 {
+            return 0.0;
+        }
+        bits as f32 / pixels as f32
+    }
+}
+
+/// Count the run lengths of all `lines`, split into codes the way they would be encoded.
+/// 
+/// `width` is needed to determine the length of the last run in each line.
+pub fn run_length_histogram(lines: &[&[u16]], width: u16) -> RunHistogram {
+    let mut histogram = RunHistogram::new();
+    for line in lines {
+        for (color, run) in run_pairs(line, width) {
+            histogram.add(color, run);
+        }
+    }
+    histogram
+}
+
+#[test]
+fn test_color_at() {
+    let line = [0, 3, 5];
+    assert_eq!(color_at(&line, 0), Color::Black);
+    assert_eq!(color_at(&line, 2), Color::Black);
+    assert_eq!(color_at(&line, 3), Color::White);
+    assert_eq!(color_at(&line, 5), Color::Black);
+    assert_eq!(color_at(&[], 7), Color::White);
+}
+
+#[test]
+fn test_from_bool_slice() {
+    let pixels = [true, true, false, true];
+    assert_eq!(from_bool_slice(&pixels, true), [0, 2, 3]);
+    assert_eq!(from_bool_slice(&pixels, false), [2, 3]);
+}
+
+#[test]
+fn test_packed_pixels_to_transitions() {
+    assert_eq!(packed_pixels_to_transitions(&[0b1101_0000, 0b1000_0000], 9), [0, 2, 3, 4, 8]);
+    // padding bits are ignored
+    assert_eq!(packed_pixels_to_transitions(&[0b0000_0111], 6), [5]);
+}
+
+#[test]
+fn test_detect_margins() {
+    let lines: [&[u16]; 5] = [&[], &[64, 70], &[3, 10], &[], &[64]];
+    assert_eq!(detect_margins(&lines, 64), (2, 2));
+    // only transitions at or beyond the width: the whole image is top margin
+    assert_eq!(detect_margins(&[&[64], &[], &[80, 90]], 64), (3, 0));
+    assert_eq!(detect_margins(&[], 64), (0, 0));
+    // no margins
+    assert_eq!(detect_margins(&[&[0], &[], &[5, 6]], 64), (0, 0));
+}
+
+#[test]
+fn test_crop_margins() {
+    let lines = vec![vec![], vec![3, 10], vec![], vec![0], vec![70]];
+    assert_eq!(crop_margins(lines, 64), [vec![3, 10], vec![], vec![0]]);
+    assert!(crop_margins(vec![vec![], vec![64]], 64).is_empty());
+    let lines = vec![vec![1], vec![2, 3]];
+    assert_eq!(crop_margins(lines.clone(), 64), lines);
+}
+
+#[test]
+fn test_histogram() {
+    let histogram = run_length_histogram(&[&[], &[10, 20]], 1728);
+    assert_eq!(histogram.white_runs[0], 1);
+    assert_eq!(histogram.white_runs[10], 1);
+    assert_eq!(histogram.white_runs[44], 1);
+    assert_eq!(histogram.black_runs[10], 1);
+    assert_eq!(histogram.white_makeup, vec![(1664, 1), (1728, 1)]);
+    assert!(histogram.black_makeup.is_empty());
+}
+
+#[test]
+fn test_split_columns() {
+    let line = [2, 5, 7, 12];
+    let (left, right) = split_columns(&line, 6);
+    assert_eq!(left, [2, 5]);
+    assert_eq!(right, [1, 6]);
+    assert_eq!(merge_columns(&left, &right, 6), line);
+
+    let (left, right) = split_columns(&line, 3);
+    assert_eq!(left, [2]);
+    assert_eq!(right, [0, 2, 4, 9]);
+    assert_eq!(merge_columns(&left, &right, 3), line);
+}
+
+#[test]
+fn test_merge_lines() {
+    // black runs meeting at the join become one run
+    assert_eq!(merge_lines(&[4], &[0, 3], 6), [4, 9]);
+    // white runs as well
+    assert_eq!(merge_lines(&[1, 2], &[3, 4], 6), [1, 2, 9, 10]);
+    assert_eq!(merge_lines(&[], &[], 6), []);
+}
+
+#[test]
+fn test_xor_lines() {
+    let a = [2, 5, 7, 12];
+    let b = [2, 6];
+    let delta = xor_lines(&a, &b, 16);
+    assert_eq!(delta, [5, 6, 7, 12]);
+    assert_eq!(apply_xor(&a, &delta, 16), b);
+    assert_eq!(line_intersection(&a, &b, 16), [0, 5, 6, 7, 12]);
+    assert_eq!(line_difference_count(&a, &b, 16), 6);
+}
+
+#[test]
+fn test_diff_lines() {
+    assert_eq!(diff_lines(&[10, 20], &[10, 20], 100), []);
+    assert_eq!(diff_lines(&[10, 20], &[12, 20, 90], 100), [
+        (10, 12, Color::Black, Color::White),
+        (90, 100, Color::White, Color::Black),
+    ]);
+    // the colors swap at 50
+    assert_eq!(diff_lines(&[40], &[50], 60), [
+        (40, 50, Color::Black, Color::White),
+    ]);
+    assert_eq!(diff_lines(&[0, 50], &[50], 60), [
+        (0, 50, Color::Black, Color::White),
+        (50, 60, Color::White, Color::Black),
+    ]);
+}
+
+#[test]
+fn test_normalize_transitions() {
+    assert_eq!(normalize_transitions(&[5, 5, 10]), [10]);
+    assert_eq!(normalize_transitions(&[0, 3, 3, 3, 8]), [0, 3, 8]);
+    assert_eq!(normalize_transitions(&[2, 4]), [2, 4]);
+    assert_eq!(deduplicate_transitions(vec![5, 5, 10]), [5, 10]);
+    assert_eq!(deduplicate_transitions(vec![0, 3, 3, 3, 8]), [0, 3, 8]);
+}
+
+#[test]
+fn test_invert_transitions() {
+    assert_eq!(invert_transitions(&[3, 5]), [0, 3, 5]);
+    assert_eq!(invert_transitions(&[0, 3, 5]), [3, 5]);
+    assert_eq!(invert_transitions(&[]), [0]);
+    assert_eq!(invert_transitions(&[0]), []);
+}
+
+#[test]
+fn test_count_color() {
+    let line = [0, 3, 5, 10];
+    assert_eq!(count_color(&line, Color::Black), 2);
+    assert_eq!(count_color(&line, Color::White), 2);
+    assert_eq!(count_color(&[], Color::White), 1);
+    assert_eq!(total_pixels_of_color(&line, 20, Color::Black), 8);
+    assert_eq!(total_pixels_of_color(&line, 20, Color::White), 12);
+}
+
+#[test]
+fn test_reverse_transitions() {
+    assert_eq!(reverse_transitions(&[2, 5], 10), [5, 8]);
+    assert_eq!(reverse_transitions(&[2, 5, 7], 10), [0, 3, 5, 8]);
+    assert_eq!(reverse_transitions(&[0, 3], 10), [7]);
+    assert_eq!(reverse_transitions(&[], 10), []);
+    for line in [&[1, 4, 6][..], &[0, 9], &[3]] {
+        assert_eq!(reverse_transitions(&reverse_transitions(line, 10), 10), line);
+    }
+}
+
+#[test]
+fn test_crop_transitions() {
+    let line = [2, 5, 8, 12];
+    assert_eq!(crop_transitions(&line, 0, 20), line);
+    assert_eq!(crop_transitions(&line, 3, 10), [0, 2, 5]);
+    assert_eq!(crop_transitions(&line, 5, 8), []);
+    assert_eq!(crop_transitions(&line, 2, 8), [0, 3]);
+    assert_eq!(crop_transitions(&line, 6, 6), []);
+}
+
+#[test]
+fn test_count() {
+    assert_eq!(count(&[]), 1);
+    assert_eq!(count(&[3, 5]), 3);
+    // the leading white run of length zero counts as well
+    assert_eq!(count(&[0, 5]), 3);
+    assert!(is_blank(&[]));
+    assert!(!is_blank(&[0]));
+    assert!(!is_blank(&[3, 5]));
+}
+
+#[test]
+fn test_last_run_end() {
+    assert_eq!(last_run_end(&[2, 5], 10), 10);
+    assert!(is_full_width(&[2, 5], 10));
+    // a transition past the width
+    assert_eq!(last_run_end(&[2, 12], 10), 12);
+    assert!(!is_full_width(&[2, 12], 10));
+    assert_eq!(last_run_end(&[], 10), 10);
+    assert!(is_full_width(&[], 10));
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the integration tests (and the bench example).
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+/// A binary PBM (P4) image.
+pub struct Pbm {
+    pub width: u16,
+    /// Packed rows, one bit per pixel with `1` for black.
+    pub rows: Vec<Vec<u8>>,
+}
+
+pub fn read_pbm(path: &Path) -> Pbm {
+    let data = fs::read(path).unwrap();
+    let mut parts = data.splitn(3, |&b| b == b'\n');
+    assert_eq!(parts.next().unwrap(), b"P4");
+    let mut size = parts.next().unwrap().splitn(2, |&b| b == b' ');
+    let width: u16 = std::str::from_utf8(size.next().unwrap()).unwrap().parse().unwrap();
+    let rows = parts.next().unwrap().chunks((width as usize + 7) / 8).map(|row| row.to_vec()).collect();
+    Pbm { width, rows }
+}
+
+/// SHA-256 of `data`, as a lowercase hex string.
+pub fn sha256(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A width and lines of sorted, distinct color changes within it.
+pub fn page() -> impl proptest::strategy::Strategy<Value=(u16, Vec<Vec<u16>>)> {
+    use proptest::prelude::*;
+    (1u16 ..= 4096).prop_flat_map(|width| {
+        let line = prop::collection::btree_set(0 .. width, 0 .. 64).prop_map(|set| set.into_iter().collect());
+        (Just(width), prop::collection::vec(line, 1 .. 8))
+    })
+}
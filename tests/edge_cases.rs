@@ -0,0 +1,414 @@
+use fax::{VecWriter, BitWriter, Bits, encoder::{Encoder, G3Encoder, encode_g4, encode_g4_horizontal}, decoder::{decode_g4, decode_g4_with_final_ref, decode_g4_with_reference, pels, decode_g3, decode_g3_with_width, decode_g3_with_options, G3DecodeOptions}, Color, FaxError};
+
+fn encode(lines: &[Vec<Color>], width: u16) -> Vec<u8> {
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in lines {
+        encoder.encode_line(line.iter().cloned(), width);
+    }
+    encoder.finish().finish()
+}
+
+fn decode(data: &[u8], width: u16) -> Vec<Vec<Color>> {
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| lines.push(pels(line, width).collect())).unwrap();
+    lines
+}
+
+fn solid(color: Color, width: u16) -> Vec<Color> {
+    vec![color; width as usize]
+}
+
+const PASS: Bits = Bits { data: 0b0001, len: 4 };
+const HORIZONTAL: Bits = Bits { data: 0b001, len: 3 };
+const V0: Bits = Bits { data: 0b1, len: 1 };
+const WHITE_10: Bits = Bits { data: 0b00111, len: 5 };
+const BLACK_10: Bits = Bits { data: 0b0000100, len: 7 };
+
+/// Decode the given G4 codes, followed by an EOFB.
+fn decode_codes(codes: &[Bits], width: u16) -> Vec<Vec<u16>> {
+    let mut writer = VecWriter::new();
+    for &code in codes.iter().chain([Bits { data: 1, len: 12 }; 2].iter()) {
+        writer.write(code);
+    }
+    let data = writer.finish();
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| lines.push(line.to_vec())).unwrap();
+    lines
+}
+
+#[test]
+fn all_black_page() {
+    let width = 1728;
+    let page = vec![solid(Color::Black, width); 100];
+    let data = encode(&page, width);
+
+    let mut lines = 0;
+    decode_g4(data.iter().cloned(), width, None, |line| {
+        assert_eq!(line, &[0]);
+        lines += 1;
+    }).unwrap();
+    assert_eq!(lines, page.len());
+    assert_eq!(decode(&data, width), page);
+}
+
+#[test]
+fn alternating_black_and_white_lines() {
+    let width = 1728;
+    let page: Vec<_> = (0 .. 100).map(|y| solid(if y % 2 == 0 { Color::Black } else { Color::White }, width)).collect();
+    assert_eq!(decode(&encode(&page, width), width), page);
+}
+
+#[test]
+fn single_pixel_wide() {
+    for &height in [1, 2, 7, 64].iter() {
+        let black = vec![vec![Color::Black]; height];
+        let white = vec![vec![Color::White]; height];
+        let alternating: Vec<_> = (0 .. height).map(|y| vec![if y % 2 == 0 { Color::Black } else { Color::White }]).collect();
+        for page in [black, white, alternating].iter() {
+            let lines = decode(&encode(page, 1), 1);
+            assert!(lines.iter().all(|line| line.len() == 1));
+            assert_eq!(&lines, page, "height {}", height);
+        }
+    }
+}
+
+#[test]
+fn all_vertical_deltas() {
+    let vertical = [
+        (-3, Bits { data: 0b0000010, len: 7 }),
+        (-2, Bits { data: 0b000010, len: 6 }),
+        (-1, Bits { data: 0b010, len: 3 }),
+        (0, Bits { data: 0b1, len: 1 }),
+        (1, Bits { data: 0b011, len: 3 }),
+        (2, Bits { data: 0b000011, len: 6 }),
+        (3, Bits { data: 0b0000011, len: 7 }),
+    ];
+    for &(delta, code) in vertical.iter() {
+        let lines = decode_codes(&[
+            // first line: horizontal mode with white 50, black 10, then V0 to the end
+            HORIZONTAL, Bits { data: 0b01010011, len: 8 }, BLACK_10, V0,
+            // second line: the change to black moves by `delta`, the others stay
+            code, V0, V0,
+        ], 100);
+        let a1 = (50 + delta) as u16;
+        assert_eq!(lines, [vec![50, 60], vec![a1, 60]], "delta {}", delta);
+    }
+}
+
+#[test]
+fn pass_at_start_of_row() {
+    // the reference has black from 10 to 20, so the pass mode skips to 20
+    let lines = decode_codes(&[
+        HORIZONTAL, WHITE_10, BLACK_10, V0,
+        PASS, HORIZONTAL, WHITE_10, BLACK_10, V0,
+    ], 100);
+    assert_eq!(lines, [vec![10, 20], vec![30, 40]]);
+
+    // with an all-white reference, b2 is at the end of the line and the line is all white
+    let lines = decode_codes(&[
+        PASS,
+        HORIZONTAL, WHITE_10, BLACK_10, V0,
+    ], 100);
+    assert_eq!(lines, [vec![], vec![10, 20]]);
+}
+
+const EOL: Bits = Bits { data: 1, len: 12 };
+const MAKEUP_2560: Bits = Bits { data: 0b000000011111, len: 12 };
+
+fn decode_g3_codes(codes: &[Bits], width: Option<u16>) -> Result<Vec<Vec<u16>>, FaxError> {
+    let mut writer = VecWriter::new();
+    for &code in [EOL].iter().chain(codes).chain([EOL; 6].iter()) {
+        writer.write(code);
+    }
+    let data = writer.finish();
+    let options = G3DecodeOptions {
+        width,
+        .. G3DecodeOptions::default()
+    };
+    let mut lines = vec![];
+    decode_g3_with_options(data.iter().cloned(), &options, |line| lines.push(line.to_vec()))?;
+    Ok(lines)
+}
+
+#[test]
+fn maximum_run_length() {
+    // white 2560 + 63, black 2560 + 63
+    let lines = decode_g3_codes(&[
+        MAKEUP_2560, Bits { data: 0b00110100, len: 8 },
+        MAKEUP_2560, Bits { data: 0b000001100111, len: 12 },
+    ], Some(5246)).unwrap();
+    // the last entry is the end of the line
+    assert_eq!(lines, [[2623, 5246]]);
+
+    // white 2560 + 64 + 0, black 2560 + 62
+    let lines = decode_g3_codes(&[
+        MAKEUP_2560, Bits { data: 0b11011, len: 5 }, Bits { data: 0b00110101, len: 8 },
+        MAKEUP_2560, Bits { data: 0b000001100110, len: 12 },
+    ], Some(5246)).unwrap();
+    assert_eq!(lines, [[2624, 5246]]);
+
+    // more than u16::MAX pixels in one run
+    let mut codes = vec![MAKEUP_2560; 26];
+    codes.push(Bits { data: 0b00110101, len: 8 });
+    assert_eq!(decode_g3_codes(&codes, None), Err(FaxError::InvalidCode));
+}
+
+#[test]
+fn reference_from_previous_page() {
+    let width = 200;
+    let page1: Vec<Vec<u16>> = (0 .. 20).map(|y| vec![y, 100, 150 - y]).collect();
+    let page2: Vec<Vec<u16>> = (0 .. 20).map(|y| vec![20 + y, 100, 130 + 2 * y]).collect();
+
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in &page1 {
+        encoder.encode_transitions(line, width);
+    }
+    let data1 = encoder.finish().finish();
+    let mut encoder = Encoder::with_reference(VecWriter::new(), page1.last().unwrap());
+    for line in &page2 {
+        encoder.encode_transitions(line, width);
+    }
+    let data2 = encoder.finish().finish();
+
+    let last = decode_g4_with_final_ref(data1.iter().cloned(), width, None, |_| ()).unwrap().unwrap();
+    assert_eq!(&last, page1.last().unwrap());
+
+    let mut lines = vec![];
+    decode_g4_with_reference(data2.iter().cloned(), width, None, &last, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, page2);
+
+    // the first line does not decode correctly relative to an all-white line
+    let mut lines = vec![];
+    let _ = decode_g4(data2.iter().cloned(), width, None, |line| lines.push(line.to_vec()));
+    assert_ne!(lines.first(), page2.first());
+}
+
+#[test]
+fn pass_to_end_of_line() {
+    let lines = decode_codes(&[
+        // white 50, black 50
+        HORIZONTAL, Bits { data: 0b01010011, len: 8 }, Bits { data: 0b000001010010, len: 12 },
+        // b1 is at 50 and b2 at the end of the line, so a single pass code completes the line
+        PASS,
+        // the next line is decoded as such
+        HORIZONTAL, WHITE_10, BLACK_10, V0,
+    ], 100);
+    assert_eq!(lines, [vec![50], vec![], vec![10, 20]]);
+}
+
+#[test]
+fn blank_page() {
+    let width = 1728;
+    // the encoder codes each line as V0
+    let page = vec![solid(Color::White, width); 10];
+    let data = encode(&page, width);
+    assert_eq!(data.len(), 5);
+    assert_eq!(decode(&data, width), page);
+
+    // a pass code per line works as well
+    let lines = decode_codes(&[PASS; 10], width);
+    assert_eq!(lines, vec![Vec::<u16>::new(); 10]);
+}
+
+#[test]
+fn mixed_g3_widths() {
+    // the three standard widths of T.4, interleaved
+    let lines: Vec<(u16, Vec<u16>)> = (0 .. 12).map(|y| {
+        let width = [1728, 2048, 2432][y % 3];
+        (width, vec![y as u16, width / 2, width - 1])
+    }).collect();
+    let mut encoder = G3Encoder::new(VecWriter::new());
+    for (width, line) in &lines {
+        encoder.encode_transitions(line, *width);
+    }
+    let data = encoder.finish().finish();
+
+    let mut decoded = vec![];
+    decode_g3(data.iter().cloned(), |line| decoded.push(line.to_vec())).unwrap();
+    // the end of each line gives its width
+    let expected: Vec<Vec<u16>> = lines.iter().map(|(width, line)| [&line[..], &[*width]].concat()).collect();
+    assert_eq!(decoded, expected);
+
+    // the first wider line is rejected
+    let mut decoded = 0;
+    assert_eq!(decode_g3_with_width(data.iter().cloned(), 1728, |_| decoded += 1), Err(FaxError::WidthMismatch));
+    assert_eq!(decoded, 1);
+    let mut decoded = 0;
+    assert_eq!(decode_g3_with_width(data.iter().cloned(), 2048, |_| decoded += 1), Err(FaxError::WidthMismatch));
+    assert_eq!(decoded, 2);
+}
+
+#[test]
+fn horizontal_to_end_of_line() {
+    const BLACK_20: Bits = Bits { data: 0b00001101000, len: 11 };
+    let next_line = [HORIZONTAL, WHITE_10, BLACK_10, V0];
+
+    // a2 is exactly at the end of the line
+    let mut codes = vec![HORIZONTAL, WHITE_10, BLACK_20];
+    codes.extend_from_slice(&next_line);
+    assert_eq!(decode_codes(&codes, 30), [vec![10], vec![10, 20]]);
+
+    // a2 is past the end, which is not stored
+    assert_eq!(decode_codes(&codes, 28), [vec![10], vec![10, 20]]);
+}
+
+#[test]
+fn only_eofb() {
+    let mut writer = VecWriter::new();
+    writer.write(EOL);
+    writer.write(EOL);
+    let data = writer.finish();
+    let mut lines = 0;
+    assert_eq!(decode_g4(data.iter().cloned(), 1728, None, |_| lines += 1), Some(()));
+    assert_eq!(lines, 0);
+}
+
+#[test]
+fn zero_length_g3_line() {
+    const WHITE_0: Bits = Bits { data: 0b00110101, len: 8 };
+    let width = 30;
+    let lines = decode_g3_codes(&[
+        WHITE_10, BLACK_10, WHITE_10, EOL,
+        // only a white run of 0, followed by the EOL
+        WHITE_0, EOL,
+        WHITE_10, BLACK_10, WHITE_10,
+    ], Some(width)).unwrap();
+    // the empty line has no end either
+    assert_eq!(lines, [vec![10, 20, 30], vec![], vec![10, 20, 30]]);
+    assert!(pels(&lines[1], width).eq(solid(Color::White, width)));
+}
+
+#[test]
+fn negative_vertical_at_start_of_row() {
+    const WHITE_3: Bits = Bits { data: 0b1000, len: 4 };
+    const BLACK_17: Bits = Bits { data: 0b0000011000, len: 10 };
+    const VL3: Bits = Bits { data: 0b0000010, len: 7 };
+    const VL2: Bits = Bits { data: 0b000010, len: 6 };
+    let lines = decode_codes(&[
+        HORIZONTAL, WHITE_3, BLACK_17, V0,
+        // b1 is at 3, so the line starts black
+        VL3, V0, V0,
+        // with the reference starting black, b1 is at 0
+        V0, V0, V0,
+        HORIZONTAL, WHITE_3, BLACK_17, V0,
+        VL2, V0, V0,
+    ], 40);
+    assert_eq!(lines, [vec![3, 20], vec![0, 20], vec![0, 20], vec![3, 20], vec![1, 20]]);
+}
+
+#[test]
+fn single_pixel_runs() {
+    let width = 1728;
+    // every pixel is a change, starting black
+    let line: Vec<u16> = (0 .. width).collect();
+    let lines = vec![line.clone(), line.clone(), vec![]];
+
+    let data = encode_g4_horizontal(&lines, width);
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded[0].len(), 1728);
+    assert_eq!(decoded, lines);
+
+    let data = encode_g4(lines.iter().map(|line| line.as_slice()), width);
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+}
+
+#[test]
+fn stale_reference_for_second_page() {
+    let width = 100;
+    let page1: Vec<Vec<u16>> = vec![vec![], vec![10, 20, 60, 70], vec![12, 22, 58, 72]];
+    let page2: Vec<Vec<u16>> = vec![vec![], vec![], vec![40, 50]];
+    let data1 = encode_g4(page1.iter().map(|l| l.as_slice()), width);
+    let data2 = encode_g4(page2.iter().map(|l| l.as_slice()), width);
+    let data: Vec<u8> = data1.iter().chain(&data2).cloned().collect();
+
+    // decoding the first page stops at its end-of-block marker
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, page1);
+
+    // the second page starts over with an all-white reference line
+    let page2_data = &data[data1.len() ..];
+    let mut lines = vec![];
+    decode_g4(page2_data.iter().cloned(), width, None, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, page2);
+
+    // The coded data cannot tell a stale reference line apart, so the result is wrong without an error:
+    // the two Vertical(0) codes of the white lines now stop at the changes 12 and 22 of the old reference,
+    // and the horizontal runs of 40 white and 10 black pixels of the third line continue that same line.
+    let mut lines = vec![];
+    let result = decode_g4_with_reference(page2_data.iter().cloned(), width, None, page1.last().unwrap(), |line| lines.push(line.to_vec()));
+    assert_eq!(result, Ok(()));
+    assert_eq!(lines, [vec![12, 22, 62, 72]]);
+}
+
+#[test]
+fn only_pass_codes() {
+    // The encoder codes a white line below a white line as Vertical(0), but a pass code,
+    // moving a0 to b2 at the end of the line, completes it just as well.
+    let lines = decode_codes(&[PASS; 5], 1728);
+    assert_eq!(lines, vec![Vec::<u16>::new(); 5]);
+}
+
+#[test]
+fn make_up_codes_with_zero_terminating_code() {
+    const WHITE_0: Bits = Bits { data: 0b00110101, len: 8 };
+    const BLACK_0: Bits = Bits { data: 0b0000110111, len: 10 };
+    let lines = decode_g3_codes(&[
+        // white 1728 + 0
+        Bits { data: 0b010011011, len: 9 }, WHITE_0,
+        EOL,
+        // white 64 + 0, black 128 + 0, white 1536 + 0
+        Bits { data: 0b11011, len: 5 }, WHITE_0,
+        Bits { data: 0b000011001000, len: 12 }, BLACK_0,
+        Bits { data: 0b010011001, len: 9 }, WHITE_0,
+        EOL,
+        // black from the start: white 0, black 1664 + 0, white 64 + 0
+        WHITE_0,
+        Bits { data: 0b0000001100100, len: 13 }, BLACK_0,
+        Bits { data: 0b11011, len: 5 }, WHITE_0,
+    ], Some(1728)).unwrap();
+    assert_eq!(lines, [vec![1728], vec![64, 192, 1728], vec![0, 1664, 1728]]);
+}
+
+#[test]
+fn vertical_0_at_start_of_row() {
+    // on an all-white reference line, b1 is the imaginary change at the width, so the line stays white
+    assert_eq!(decode_codes(&[V0], 100), [Vec::<u16>::new()]);
+
+    // below a line starting black, b1 is at 0, so the line starts black as well
+    let white_0 = Bits { data: 0b00110101, len: 8 };
+    let lines = decode_codes(&[
+        HORIZONTAL, white_0, BLACK_10, V0,
+        V0, V0, V0,
+    ], 100);
+    assert_eq!(lines, [[0, 10], [0, 10]]);
+}
+
+#[test]
+fn consecutive_make_up_codes() {
+    const WHITE_0: Bits = Bits { data: 0b00110101, len: 8 };
+    const BLACK_0: Bits = Bits { data: 0b0000110111, len: 10 };
+    const WHITE_MAKEUP_1728: Bits = Bits { data: 0b010011011, len: 9 };
+    const WHITE_MAKEUP_64: Bits = Bits { data: 0b11011, len: 5 };
+    // the extended make-up codes are the same for both colors
+    const MAKEUP_1792: Bits = Bits { data: 0b00000001000, len: 11 };
+    const MAKEUP_2432: Bits = Bits { data: 0b000000011101, len: 12 };
+    const MAKEUP_2496: Bits = Bits { data: 0b000000011110, len: 12 };
+    let lines = decode_g3_codes(&[
+        // white 1728 + 64 + 0
+        WHITE_MAKEUP_1728, WHITE_MAKEUP_64, WHITE_0,
+        // black 2560 + 63
+        MAKEUP_2560, Bits { data: 0b000001100111, len: 12 },
+        // white 2560 + 2432 + 8
+        MAKEUP_2560, MAKEUP_2432, Bits { data: 0b10011, len: 5 },
+        EOL,
+        // white 0, black 1792 + 0
+        WHITE_0, MAKEUP_1792, BLACK_0,
+        // white 2560 + 2560 + 2496 + 7
+        MAKEUP_2560, MAKEUP_2560, MAKEUP_2496, Bits { data: 0b1111, len: 4 },
+    ], Some(9415)).unwrap();
+    assert_eq!(lines, [[1792, 4415, 9415], [0, 1792, 9415]]);
+}
@@ -0,0 +1,158 @@
+use fax::{VecWriter, encoder::{Encoder, encode_g3, encode_g4, encode_g4_horizontal}, decoder::{decode_g4, pels_packed, inspect_g4, G4Event, Mode}, transitions::packed_pixels_to_transitions, Color, ByteReader};
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::{Pbm, read_pbm};
+
+fn encode(pbm: &Pbm) -> Vec<u8> {
+    encode_rows(pbm, pbm.rows.len())
+}
+
+fn encode_rows(pbm: &Pbm, n: usize) -> Vec<u8> {
+    let mut encoder = Encoder::new(VecWriter::new());
+    for row in &pbm.rows[.. n] {
+        let pels = ByteReader::new(row.iter().cloned()).into_bits().take(pbm.width as usize)
+            .map(|b| if b { Color::Black } else { Color::White });
+        encoder.encode_line(pels, pbm.width);
+    }
+    encoder.finish().finish()
+}
+
+/// The streams in `stream/` were produced by other encoders.
+/// Our output has to decode to the same image and must not be much larger.
+#[test]
+fn compare_with_reference_streams() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("stream");
+    for n in ["4", "6", "33", "44", "65", "71"].iter() {
+        let pbm = read_pbm(&data_path.join(n).with_extension("pbm"));
+        let reference = fs::read(data_path.join(n)).unwrap();
+
+        let data = encode(&pbm);
+        let mut rows = vec![];
+        decode_g4(data.iter().cloned(), pbm.width, None, |line| rows.push(pels_packed(line, pbm.width))).unwrap();
+        assert!(rows == pbm.rows, "stream {} does not round-trip", n);
+
+        let ratio = data.len() as f64 / reference.len() as f64;
+        assert!(ratio <= 1.05, "stream {}: {} bytes vs. {} bytes of the reference", n, data.len(), reference.len());
+    }
+}
+
+/// The reference streams leave out trailing white lines.
+/// Coding the same lines, the mode selection has to produce the same output,
+/// except for a difference in padding at the end.
+#[test]
+fn bit_exact_with_reference_streams() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("stream");
+    for n in ["4", "6", "33", "44", "65", "71"].iter() {
+        let pbm = read_pbm(&data_path.join(n).with_extension("pbm"));
+        let reference = fs::read(data_path.join(n)).unwrap();
+        let mut lines = 0;
+        decode_g4(reference.iter().cloned(), pbm.width, None, |_| lines += 1).unwrap();
+        assert!(pbm.rows[lines ..].iter().all(|row| packed_pixels_to_transitions(row, pbm.width).is_empty()), "stream {} leaves out lines that are not white", n);
+
+        let data = encode_rows(&pbm, lines);
+        let mut rows = vec![];
+        decode_g4(data.iter().cloned(), pbm.width, None, |line| rows.push(pels_packed(line, pbm.width))).unwrap();
+        assert!(rows == pbm.rows[.. lines], "stream {} does not round-trip", n);
+
+        let common = data.iter().zip(reference.iter()).take_while(|(a, b)| a == b).count();
+        assert!(common + 2 >= reference.len(), "stream {} differs at byte {} of {}", n, common, reference.len());
+        assert!(data.len() * 100 <= reference.len() * 101, "stream {}: {} bytes vs. {} bytes", n, data.len(), reference.len());
+    }
+}
+
+#[test]
+fn all_mode_types() {
+    let width = 1728;
+    let lines: Vec<Vec<u16>> = vec![
+        // horizontal, as the reference is white
+        vec![100, 200],
+        // vertical, within 3 pixels of the line above
+        vec![102, 199],
+        vec![101, 202, 1000, 1003],
+        // pass, the runs above end before anything changes here
+        vec![],
+        vec![500, 600],
+        vec![497, 603],
+        vec![1200, 1300, 1700, 1727],
+        vec![1201, 1299, 1702, 1727],
+        // pass over the first run above, vertical for the second
+        vec![1700, 1727],
+        vec![],
+    ];
+    let data = encode_g4(lines.iter().map(|line| line.as_slice()), width);
+
+    let modes: Vec<Mode> = inspect_g4(&data, width).into_iter().filter_map(|e| match e {
+        G4Event::ModeCode(mode) => Some(mode),
+        _ => None,
+    }).collect();
+    assert!(modes.contains(&Mode::Pass));
+    assert!(modes.contains(&Mode::Horizontal));
+    assert!(modes.iter().any(|m| matches!(m, Mode::Vertical(d) if *d != 0)));
+
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+}
+
+#[test]
+fn horizontal_to_end_of_odd_line() {
+    // white 2, black 3: a2 is at the end of the line
+    let lines = vec![vec![2], vec![2]];
+    let data = encode_g4_horizontal(&lines, 5);
+    let events = inspect_g4(&data, 5);
+    assert_eq!(events[.. 4], [
+        G4Event::LineStart(0),
+        G4Event::ModeCode(Mode::Horizontal),
+        G4Event::ColorTransition { position: 2, color: Color::Black },
+        G4Event::LineEnd(0),
+    ]);
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), 5, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+
+    // the same pixels through the regular encoder
+    let mut encoder = Encoder::new(VecWriter::new());
+    for _ in 0 .. 2 {
+        encoder.encode_line([Color::White, Color::White, Color::Black, Color::Black, Color::Black].iter().cloned(), 5);
+    }
+    let data = encoder.finish().finish();
+    let mut decoded = vec![];
+    decode_g4(data.iter().cloned(), 5, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+}
+
+/// 24 pixels wide, one bit per pixel with `1` for black.
+const LIBTIFF_PATTERN: [[u8; 3]; 3] = [
+    [0xF0, 0x0F, 0x00],
+    [0x0F, 0xF0, 0xF0],
+    [0x00, 0xFF, 0x0F],
+];
+
+fn libtiff_pattern_lines() -> Vec<Vec<u16>> {
+    LIBTIFF_PATTERN.iter().map(|row| packed_pixels_to_transitions(row, 24)).collect()
+}
+
+/// The expected bytes are the strip libtiff 4.5.0 writes for `LIBTIFF_PATTERN`
+/// (`TIFFWriteScanline` with Compression=3, T4Options=0, FillOrder=1, Photometric=MinIsWhite).
+/// Being a TIFF strip, it has no RTC.
+#[test]
+fn g3_matches_libtiff() {
+    const LIBTIFF: [u8; 13] = [0x00, 0x13, 0x57, 0x37, 0x30, 0x01, 0xb1, 0x6d, 0xd8, 0x00, 0xcc, 0x5b, 0x60];
+    let lines = libtiff_pattern_lines();
+    let data = encode_g3(lines.iter().map(|line| line.as_slice()), 24);
+    assert_eq!(data[.. LIBTIFF.len()], LIBTIFF);
+    // the RTC (six EOLs) starts within the last byte, where libtiff pads with zeros
+    assert_eq!(data[LIBTIFF.len() ..], [0x02, 0x00, 0x20, 0x02, 0x00, 0x20, 0x02, 0x00, 0x20]);
+}
+
+/// The expected bytes are the strip libtiff 4.5.0 writes for `LIBTIFF_PATTERN`
+/// (`TIFFWriteScanline` with Compression=4, FillOrder=1, Photometric=MinIsWhite).
+#[test]
+fn g4_matches_libtiff() {
+    const LIBTIFF: [u8; 13] = [0x26, 0xac, 0xcd, 0xcd, 0x8a, 0x6d, 0xcc, 0xc5, 0x36, 0xc0, 0x04, 0x00, 0x40];
+    let lines = libtiff_pattern_lines();
+    let data = encode_g4(lines.iter().map(|line| line.as_slice()), 24);
+    assert_eq!(data, LIBTIFF);
+}
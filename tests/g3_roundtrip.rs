@@ -0,0 +1,47 @@
+use fax::{encoder::encode_g3, decoder::{decode_g3_with_options, G3DecodeOptions}};
+use proptest::prelude::*;
+
+mod common;
+use common::page;
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+    #[test]
+    fn roundtrip((width, lines) in page()) {
+        let data = encode_g3(lines.iter().map(|line| line.as_slice()), width);
+        let options = G3DecodeOptions {
+            width: Some(width),
+            omit_line_end: true,
+            .. G3DecodeOptions::default()
+        };
+        let mut decoded = vec![];
+        decode_g3_with_options(data.iter().cloned(), &options, |line| decoded.push(line.to_vec())).unwrap();
+        prop_assert_eq!(decoded, lines);
+    }
+
+    /// Zeroing a byte has to be noticed.
+    #[test]
+    fn corrupt_byte((width, lines) in page(), pos in any::<prop::sample::Index>()) {
+        let mut data = encode_g3(lines.iter().map(|line| line.as_slice()), width);
+        // not in the RTC, which takes the last 9 bytes
+        let pos = pos.index(data.len() - 9);
+        prop_assume!(data[pos] != 0);
+        data[pos] = 0;
+
+        let options = G3DecodeOptions {
+            width: Some(width),
+            strict: true,
+            omit_line_end: true,
+            .. G3DecodeOptions::default()
+        };
+        // Strict mode does not catch everything: zeros in front of an EOL are fill bits,
+        // so the damaged bits can still form a valid page of different lines. For example, with
+        // a width of 2, zeroing the end of the leading EOL of `[[0]]` makes it resynchronize
+        // on a later 1 bit, and the rest of the codes read as a single white run: `[[]]`.
+        // Getting the same lines back would mean that the damage went unnoticed.
+        let mut decoded = vec![];
+        let result = decode_g3_with_options(data.iter().cloned(), &options, |line| decoded.push(line.to_vec()));
+        prop_assert!(result.is_err() || decoded != lines);
+    }
+}
@@ -0,0 +1,17 @@
+use fax::{encoder::encode_g4, decoder::decode_g4};
+use proptest::prelude::*;
+
+mod common;
+use common::page;
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+    #[test]
+    fn roundtrip((width, lines) in page()) {
+        let data = encode_g4(lines.iter().map(|line| line.as_slice()), width);
+        let mut decoded = vec![];
+        decode_g4(data.iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+        prop_assert_eq!(decoded, lines);
+    }
+}
@@ -0,0 +1,56 @@
+use fax::{VecWriter, Color, encoder::{encode_g4, Encoder}, decoder::decode_g4};
+use std::time::{Duration, Instant};
+
+/// A soft performance contract, not run by default.
+///
+/// The limit holds on a 2020 laptop with an optimized build: `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn decode_blank_page() {
+    let width = 1728;
+    let height = 2200;
+    let page = vec![vec![]; height];
+    let data = encode_g4(page.iter().map(|line: &Vec<u16>| line.as_slice()), width);
+
+    for _ in 0 .. 100 {
+        let start = Instant::now();
+        let mut lines = 0;
+        decode_g4(data.iter().cloned(), width, None, |_| lines += 1).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(lines, height);
+        assert!(elapsed < Duration::from_millis(5), "decoding took {:?}", elapsed);
+    }
+}
+
+/// Blank lines given as color changes skip the pixel by pixel scan.
+#[test]
+#[ignore]
+fn encode_blank_page() {
+    let width = 1728;
+    let height = 2200;
+    let row = vec![Color::White; width as usize];
+
+    // the fastest of several runs, to be less sensitive to other load
+    let mut transitions = Duration::MAX;
+    let mut pixels = Duration::MAX;
+    for _ in 0 .. 10 {
+        let start = Instant::now();
+        let mut encoder = Encoder::new(VecWriter::new());
+        for _ in 0 .. height {
+            encoder.encode_transitions(&[], width);
+        }
+        let data1 = encoder.finish().finish();
+        transitions = transitions.min(start.elapsed());
+
+        let start = Instant::now();
+        let mut encoder = Encoder::new(VecWriter::new());
+        for _ in 0 .. height {
+            encoder.encode_line(row.iter().cloned(), width);
+        }
+        let data2 = encoder.finish().finish();
+        pixels = pixels.min(start.elapsed());
+
+        assert_eq!(data1, data2);
+    }
+    assert!(transitions * 10 <= pixels, "{:?} from color changes, {:?} from pixels", transitions, pixels);
+}
@@ -0,0 +1,43 @@
+use fax::decoder::{decode_g3_with_options, pels_packed, G3DecodeOptions};
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::{read_pbm, sha256};
+
+/// Group 3 coded pages and the SHA-256 of their packed pixels.
+/// 
+/// `33.g3` is the page of `33.pbm` (a scanned document), as coded by libtiff 4.5.0:
+/// the raw strip of a TIFF written with Compression=3, T4Options=0, FillOrder=1 and Photometric=MinIsWhite.
+const G3_FIXTURES: [(&str, &str); 1] = [
+    ("33.g3", "a9c4ca6aedff0738e24135832615a38146d21f7cb72dbe5674187f41352a7d2a"),
+];
+
+/// Each fixture has to decode to the image in the `.pbm` file of the same name.
+#[test]
+fn decode_g3_fixtures() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("stream");
+    let mut decoded = 0;
+    for &(name, hash) in G3_FIXTURES.iter() {
+        let path = data_path.join(name);
+        let pbm = read_pbm(&path.with_extension("pbm"));
+        let width = pbm.width;
+        let data = fs::read(&path).unwrap();
+
+        let options = G3DecodeOptions {
+            width: Some(width),
+            omit_line_end: true,
+            .. G3DecodeOptions::default()
+        };
+        let mut rows = vec![];
+        decode_g3_with_options(data.iter().cloned(), &options, |line| rows.push(pels_packed(line, width)))
+            .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+        assert_eq!(rows.len(), pbm.rows.len(), "{:?}", path);
+        for (y, (row, ref_row)) in rows.iter().zip(&pbm.rows).enumerate() {
+            assert!(row == ref_row, "{:?}: line {} does not match the reference", path, y);
+        }
+        assert_eq!(sha256(&rows.concat()), hash, "{:?}", path);
+        decoded += 1;
+    }
+    assert_eq!(decoded, G3_FIXTURES.len());
+}